@@ -1,12 +1,15 @@
-use std::{env, net::UdpSocket, time::Duration};
+use std::{env, net::UdpSocket, thread, time::Duration};
 
 use messages::{
+    PROTOCOL_VERSION,
     client::ClientMessages,
-    server::{Bullet, ServerMessages, Tank, Wall},
+    server::{Bullet, DisconnectReason, MAX_MESSAGE_SIZE, Map, Parameters, ServerMessages, Tank},
 };
 
-type FnMapChange = dyn Fn(&[Wall]) -> Option<ClientMessages>;
-type FnStateChange = dyn Fn(&[Tank], &[Bullet]) -> Option<ClientMessages>;
+type FnWelcome = dyn Fn(u32, &Parameters) -> Option<ClientMessages>;
+type FnMapChange = dyn Fn(&Map) -> Option<ClientMessages>;
+/// Receives the player's own tank (if it is alive), the enemy tanks and the bullets.
+type FnStateChange = dyn Fn(Option<&Tank>, &[Tank], &[Bullet]) -> Option<ClientMessages>;
 
 type ClientError = String;
 
@@ -15,13 +18,20 @@ pub struct TankClient<'a> {
 
     server: u16,
     self_port: u16,
+    /// Team to join, read from the `TEAM` environment variable.
+    team: Option<u8>,
 
+    pub handle_welcome: &'a FnWelcome,
     pub handle_map_change: &'a FnMapChange,
     pub handle_state_change: &'a FnStateChange,
 }
 
 impl<'a> TankClient<'a> {
-    pub fn new(handle_map_change: &'a FnMapChange, handle_state_change: &'a FnStateChange) -> Self {
+    pub fn new(
+        handle_welcome: &'a FnWelcome,
+        handle_map_change: &'a FnMapChange,
+        handle_state_change: &'a FnStateChange,
+    ) -> Self {
         let self_port = env::var("SELF-PORT")
             .unwrap_or("4001".to_owned())
             .parse()
@@ -31,6 +41,8 @@ impl<'a> TankClient<'a> {
             .parse()
             .unwrap_or(4000);
 
+        let team = env::var("TEAM").ok().and_then(|team| team.parse().ok());
+
         let socket = UdpSocket::bind(("127.0.0.1", self_port)).expect("could not bind port");
         socket
             .set_read_timeout(Some(Duration::new(5, 0)))
@@ -40,6 +52,8 @@ impl<'a> TankClient<'a> {
             socket,
             server,
             self_port,
+            team,
+            handle_welcome,
             handle_map_change,
             handle_state_change,
         }
@@ -48,6 +62,7 @@ impl<'a> TankClient<'a> {
     fn connect(&self) -> Result<(), ClientError> {
         let msg = ClientMessages::Connect {
             self_port: self.self_port,
+            team: self.team,
         };
         let msg_vec = Vec::from(&msg);
 
@@ -57,40 +72,67 @@ impl<'a> TankClient<'a> {
             .map_err(|err| format!("Could not connect to server at {}\n{err:?}", self.server))
     }
 
+    fn respond(&self, msg: Option<ClientMessages>, secret: u128) -> Result<(), ClientError> {
+        if let Some(mut msg) = msg {
+            msg.set_port(self.self_port);
+            msg.set_secret(secret);
+            self.socket
+                .send_to(&Vec::from(&msg), ("127.0.0.1", self.server))
+                .map_err(|err| format!("Could not send the message to the server:\n{err}"))?;
+        }
+        Ok(())
+    }
+
     pub fn run(&self) -> Result<(), ClientError> {
         loop {
             self.connect()?;
 
-            let mut buf = [0; 4885]; // at minimum 4883
-
-            while self.socket.recv(&mut buf).is_ok() {
-                match ServerMessages::try_from(&buf[..]) {
-                    Ok(ServerMessages::MapChange { secret, walls }) => {
-                        if let Some(mut msg) = (self.handle_map_change)(&walls) {
-                            msg.set_port(self.self_port);
-                            msg.set_secret(secret);
-                            self.socket
-                                .send_to(&Vec::from(&msg), ("127.0.0.1", self.server))
-                                .map_err(|err| {
-                                    format!("Could not send the message to the server:\n{err}")
-                                })?;
+            let mut buf = [0; MAX_MESSAGE_SIZE];
+
+            while let Ok(len) = self.socket.recv(&mut buf) {
+                match ServerMessages::try_from(&buf[..len]) {
+                    Ok(ServerMessages::Welcome {
+                        version,
+                        player_id,
+                        secret,
+                        parameters,
+                    }) => {
+                        if version != PROTOCOL_VERSION {
+                            Err(format!(
+                                "server speaks protocol version {version}, expected {PROTOCOL_VERSION}"
+                            ))?;
                         }
+                        self.respond((self.handle_welcome)(player_id, &parameters), secret)?
+                    }
+                    Ok(ServerMessages::MapChange { secret, map }) => {
+                        self.respond((self.handle_map_change)(&map), secret)?
                     }
                     Ok(ServerMessages::State {
                         secret,
-                        tanks,
+                        own_tank,
+                        mut tanks,
                         bullets,
-                    }) => if let Some(mut msg) = (self.handle_state_change)(&tanks, &bullets) {
-                            msg.set_port(self.self_port);
-                            msg.set_secret(secret);
-                            self.socket
-                                .send_to(&Vec::from(&msg), ("127.0.0.1", self.server))
-                                .map_err(|err| {
-                                    format!("Could not send the message to the server:\n{err}")
-                                })?;
-                    },
-                    Ok(ServerMessages::Disconnected) => self.connect()?,
-                    Err(err) => Err(format!("received an ill-formatted message:\n{err:?}"))?,
+                        ..
+                    }) => {
+                        let own_tank = own_tank
+                            .filter(|&index| usize::from(index) < tanks.len())
+                            .map(|index| tanks.remove(usize::from(index)));
+                        self.respond(
+                            (self.handle_state_change)(own_tank.as_ref(), &tanks, &bullets),
+                            secret,
+                        )?
+                    }
+                    Ok(ServerMessages::Disconnected { reason }) => {
+                        if matches!(
+                            reason,
+                            DisconnectReason::ServerFull | DisconnectReason::ServerShutdown
+                        ) {
+                            // give the server some time before trying again
+                            thread::sleep(Duration::new(1, 0));
+                        }
+                        self.connect()?
+                    }
+                    Err(err) => Err(format!("received an ill-formatted message:\n{err}"))?,
                 }
             }
         }
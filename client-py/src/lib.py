@@ -69,52 +69,71 @@ class ClientMessage:
         )
 
 
-def parse_wall(bytes: bytes) -> tuple[Vector, Vector] | None:
-    assert len(bytes) == 16
+def parse_wall(bytes: bytes) -> tuple[Vector, Vector, Vector] | None:
+    assert len(bytes) == 24
     return (
-        Vector(unpack("f", bytes[0:4])[0], unpack("f", bytes[4:8])[0]),
-        Vector(unpack("f", bytes[8:12])[0], unpack("f", bytes[12:16])[0]),
+        Vector(unpack(">f", bytes[0:4])[0], unpack(">f", bytes[4:8])[0]),
+        Vector(unpack(">f", bytes[8:12])[0], unpack(">f", bytes[12:16])[0]),
+        Vector(unpack(">f", bytes[16:20])[0], unpack(">f", bytes[20:24])[0]),
     )
 
 
-def parse_map_change(bytes: bytes) -> list[tuple[Vector, Vector]] | None:
-    wall_count = bytes[0]
+def parse_map_change(bytes: bytes) -> tuple[str, list[tuple[Vector, Vector, Vector]], list[Vector]] | None:
+    if bytes[0] != 1:  # map format version
+        return None
+    name_length = bytes[1]
+    name = bytes[2 : 2 + name_length].decode(errors="replace")
+    start = 2 + name_length + 16  # skip the bounding box
+    wall_count = bytes[start]
     walls = []
     for i in range(wall_count):
-        walls.append(parse_wall(bytes[1 + 16 * i : 17 + 16 * i]))
-    return walls
+        walls.append(parse_wall(bytes[start + 1 + 24 * i : start + 25 + 24 * i]))
+    start = start + 1 + 24 * wall_count
+    spawn_count = bytes[start]
+    spawns = []
+    for i in range(spawn_count):
+        offset = start + 1 + 8 * i
+        spawns.append(
+            Vector(
+                unpack(">f", bytes[offset : offset + 4])[0],
+                unpack(">f", bytes[offset + 4 : offset + 8])[0],
+            )
+        )
+    return (name, walls, spawns)
 
 
 def parse_tank(bytes: bytes):
-    assert len(bytes) == 24
+    assert len(bytes) == 33
+    # bytes[0:4] is the player id, bytes[4] the team (255 when playing free-for-all)
     return (
-        Vector(unpack("f", bytes[0:4])[0], unpack("f", bytes[4:8])[0]),
-        Vector(unpack("f", bytes[8:12])[0], unpack("f", bytes[12:16])[0]),
-        Vector(unpack("f", bytes[16:20])[0], unpack("f", bytes[20:24])[0]),
+        Vector(unpack(">f", bytes[5:9])[0], unpack(">f", bytes[9:13])[0]),
+        Vector(unpack(">f", bytes[13:17])[0], unpack(">f", bytes[17:21])[0]),
+        Vector(unpack(">f", bytes[21:25])[0], unpack(">f", bytes[25:29])[0]),
+        unpack(">f", bytes[29:33])[0],
     )
 
 
 def parse_bullet(bytes: bytes):
     assert len(bytes) == 16
     return (
-        Vector(unpack("f", bytes[0:4])[0], unpack("f", bytes[4:8])[0]),
-        Vector(unpack("f", bytes[8:12])[0], unpack("f", bytes[12:16])[0]),
+        Vector(unpack(">f", bytes[0:4])[0], unpack(">f", bytes[4:8])[0]),
+        Vector(unpack(">f", bytes[8:12])[0], unpack(">f", bytes[12:16])[0]),
     )
 
 
 def parse_state_change(
     bytes: bytes,
-) -> tuple[list[tuple[Vector, Vector, Vector]], list[tuple[Vector, Vector]]] | None:
+) -> tuple[list[tuple[Vector, Vector, Vector, float]], list[tuple[Vector, Vector]]] | None:
     tank_count = bytes[0]
     tanks = []
     for i in range(tank_count):
-        tanks.append(parse_tank(bytes[1 + i * 24 : 25 + i * 24]))
-    bullet_count = bytes[1 + tank_count * 24]
+        tanks.append(parse_tank(bytes[1 + i * 33 : 34 + i * 33]))
+    bullet_count = bytes[1 + tank_count * 33]
     bullets = []
     for i in range(bullet_count):
         bullets.append(
             parse_bullet(
-                bytes[1 + tank_count * 24 + i * 16 : 17 + tank_count * 24 + i * 16]
+                bytes[2 + tank_count * 33 + i * 16 : 18 + tank_count * 33 + i * 16]
             )
         )
     return (tanks, bullets)
@@ -131,9 +150,13 @@ class Client:
     socket: SocketType
     self_port: int
     secret: int = 0
-    __on_map_change: Callable[[list[tuple[Vector, Vector]]], ClientMessage | None]
+    player_id: int = 0
+    __on_map_change: Callable[
+        [tuple[str, list[tuple[Vector, Vector, Vector]], list[Vector]]],
+        ClientMessage | None,
+    ]
     __on_state_change: Callable[
-        [list[tuple[Vector, Vector, Vector]], list[tuple[Vector, Vector]]],
+        [list[tuple[Vector, Vector, Vector, float]], list[tuple[Vector, Vector]]],
         ClientMessage | None,
     ]
 
@@ -141,12 +164,13 @@ class Client:
         self.socket = socket(type=SOCK_DGRAM)
         self.self_port = int(getenv("SELF-PORT") or "4001")
         self.server_port = int(getenv("SERVER") or "4000")
+        self.team = int(getenv("TEAM") or "255")
         self.socket.bind(("127.0.0.1", self.self_port))
         self.__on_map_change = map_change_callback
         self.__on_state_change = state_change_callback
 
     def _connect(self):
-        msg = [0, int(self.self_port / 256) % 256, self.self_port % 256]
+        msg = [0, int(self.self_port / 256) % 256, self.self_port % 256, self.team]
         print(msg)
         self.socket.sendto(bytes(msg), ("127.0.0.1", self.server_port))
 
@@ -154,13 +178,13 @@ class Client:
         while True:
             print("Connecting...")
             self._connect()
-            while incoming := self.socket.recv(4885):
+            while incoming := self.socket.recv(8452):
                 match incoming[0]:
                     case 0:  # map change
                         self.secret = parse_secret(incoming[1:17])
-                        walls = parse_map_change(incoming[17:])
-                        if walls:
-                            msg = self.__on_map_change(walls)
+                        map = parse_map_change(incoming[17:])
+                        if map:
+                            msg = self.__on_map_change(map)
                             if msg:
                                 self.socket.sendto(
                                     msg.to_bytes(self.self_port, self.secret),
@@ -168,13 +192,13 @@ class Client:
                                 )
                     case 1:  # state
                         self.secret = parse_secret(incoming[1:17])
-                        parsed = parse_state_change(incoming[17:])
+                        parsed = parse_state_change(incoming[22:])
                         if parsed:
                             (tanks, bullets) = parsed
                             msg = self.__on_state_change(tanks, bullets)
                             if msg:
                                 print(
-                                    ["pos: " + str(a) + ", dir: " + str(b) + ", tur: " + str(c) for (a, b, c) in tanks],
+                                    ["pos: " + str(a) + ", dir: " + str(b) + ", tur: " + str(c) + ", hp: " + str(d) for (a, b, c, d) in tanks],
                                     ["pos: " + str(a) + ", dir: " + str(b) for (a, b) in bullets],
                                     "->",
                                     str(msg),
@@ -190,7 +214,12 @@ class Client:
                                     ("127.0.0.1", self.server_port),
                                 )
                     case 2:  # disconnect
+                        if incoming[1] in (2, 3):  # server shutdown or full
+                            sleep(1)
                         self._connect()
+                    case 3:  # welcome
+                        self.player_id = unpack(">I", incoming[2:6])[0]
+                        self.secret = parse_secret(incoming[6:22])
                     case _:
                         print("wut??", incoming)
             sleep(0.1)
@@ -1,12 +1,14 @@
 from lib import Client, ClientMessage, Vector
 
 
-def on_map_change(walls: list[tuple[Vector, Vector]]) -> ClientMessage | None:
+def on_map_change(
+    map: tuple[str, list[tuple[Vector, Vector, Vector]], list[Vector]],
+) -> ClientMessage | None:
     pass
 
 
 def on_state_change(
-    tanks: list[tuple[Vector, Vector, Vector]], bullets: list[tuple[Vector, Vector]]
+    tanks: list[tuple[Vector, Vector, Vector, float]], bullets: list[tuple[Vector, Vector]]
 ) -> ClientMessage | None:
     return ClientMessage((50, 20), 1000, True)
 
@@ -1,22 +1,34 @@
 use client_lib_rs::TankClient;
 use messages::{
     client::ClientMessages,
-    server::{Bullet, Tank, Wall},
+    server::{Bullet, Map, Parameters, Tank},
 };
 
-fn handle_map_change(walls: &[Wall]) -> Option<ClientMessages> {
+fn handle_welcome(_player_id: u32, _parameters: &Parameters) -> Option<ClientMessages> {
+    // Nothing to answer yet, store the player id and parameters here if the bot needs them.
+    None
+}
+fn handle_map_change(map: &Map) -> Option<ClientMessages> {
+    todo!("Handle the map changing to:\n{:?}", map)
+}
+fn handle_state_change(
+    own_tank: Option<&Tank>,
+    enemies: &[Tank],
+    bullets: &[Bullet],
+) -> Option<ClientMessages> {
     todo!(
-        "Handle the map changing to have the following walls:\n{:?}",
-        walls
+        "Handle state change:\n{:?}\n{:?}\n{:?}\n",
+        own_tank,
+        enemies,
+        bullets
     )
 }
-fn handle_state_change(tanks: &[Tank], bullets: &[Bullet]) -> Option<ClientMessages> {
-    todo!("Handle state change:\n{:?}\n{:?}\n", tanks, bullets)
-}
 
 fn main() {
     loop {
-        if let Err(err) = TankClient::new(&handle_map_change, &handle_state_change).run() {
+        if let Err(err) =
+            TankClient::new(&handle_welcome, &handle_map_change, &handle_state_change).run()
+        {
             log::error!("Something failed when running:\n{err}");
         }
     }
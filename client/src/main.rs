@@ -1,17 +1,21 @@
 use client_lib_rs::TankClient;
 use messages::{
     client::ClientMessages,
-    server::{Bullet, Tank, Wall},
+    server::{Bullet, Map, Parameters, Tank},
 };
-use rand::{random, random_bool, random_range};
+use rand::random_bool;
 
-fn handle_map_change(walls: &[Wall]) -> Option<ClientMessages> {
-    todo!(
-        "Handle the map changing to have the following walls:\n{:?}",
-        walls
-    )
+fn handle_welcome(_player_id: u32, _parameters: &Parameters) -> Option<ClientMessages> {
+    None
 }
-fn handle_state_change(_tanks: &[Tank], _bullets: &[Bullet]) -> Option<ClientMessages> {
+fn handle_map_change(_map: &Map) -> Option<ClientMessages> {
+    None
+}
+fn handle_state_change(
+    _own_tank: Option<&Tank>,
+    _enemies: &[Tank],
+    _bullets: &[Bullet],
+) -> Option<ClientMessages> {
     Some(ClientMessages::control(
         [
             if random_bool(0.6) { 1000.0 } else { -1000.0 },
@@ -25,7 +29,9 @@ fn handle_state_change(_tanks: &[Tank], _bullets: &[Bullet]) -> Option<ClientMes
 
 fn main() {
     loop {
-        if let Err(err) = TankClient::new(&handle_map_change, &handle_state_change).run() {
+        if let Err(err) =
+            TankClient::new(&handle_welcome, &handle_map_change, &handle_state_change).run()
+        {
             log::error!("Something failed when running:\n{err}");
         }
     }
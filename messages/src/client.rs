@@ -1,7 +1,11 @@
+use crate::{DecodeError, Reader};
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum ClientMessages {
     Connect {
         self_port: u16,
+        /// Team the player wants to join, the server picks one if absent or invalid.
+        team: Option<u8>,
     },
     Control {
         // +1 byte
@@ -15,7 +19,17 @@ pub enum ClientMessages {
 
 impl ClientMessages {
     pub fn connect() -> Self {
-        Self::Connect { self_port: 0 }
+        Self::Connect {
+            self_port: 0,
+            team: None,
+        }
+    }
+
+    pub fn connect_to_team(team: u8) -> Self {
+        Self::Connect {
+            self_port: 0,
+            team: Some(team),
+        }
     }
 
     pub fn control(
@@ -34,7 +48,7 @@ impl ClientMessages {
 
     pub fn set_port(&mut self, n_self_port: u16) {
         match self {
-            ClientMessages::Connect { self_port }
+            ClientMessages::Connect { self_port, .. }
             | ClientMessages::Control {
                 self_port,
                 secret: _,
@@ -46,9 +60,8 @@ impl ClientMessages {
     }
 
     pub fn set_secret(&mut self, n_secret: u128) {
-        match self {
-            ClientMessages::Control { self_port: _, secret, tracks_acceleration_target: _, turret_acceleration_target: _, shoot: _ } => *secret = n_secret,
-            _ => (),
+        if let ClientMessages::Control { secret, .. } = self {
+            *secret = n_secret
         }
     }
 }
@@ -56,8 +69,13 @@ impl ClientMessages {
 impl From<&ClientMessages> for Vec<u8> {
     fn from(value: &ClientMessages) -> Self {
         match value {
-            ClientMessages::Connect { self_port } => {
-                vec![0x00, (self_port >> 8) as u8, *self_port as u8]
+            ClientMessages::Connect { self_port, team } => {
+                vec![
+                    0x00,
+                    (self_port >> 8) as u8,
+                    *self_port as u8,
+                    team.unwrap_or(u8::MAX),
+                ]
             }
             ClientMessages::Control {
                 self_port,
@@ -80,26 +98,24 @@ impl From<&ClientMessages> for Vec<u8> {
 }
 
 impl TryFrom<&[u8]> for ClientMessages {
-    type Error = ();
+    type Error = DecodeError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        match value[0] {
+        let mut reader = Reader::new(value);
+        match reader.u8()? {
             0x00 => Ok(ClientMessages::Connect {
-                self_port: ((value[1] as u16) << 8) + value[2] as u16,
+                self_port: reader.u16()?,
+                // clients predating teams do not send this byte
+                team: reader.u8().ok().filter(|&team| team != u8::MAX),
             }),
             0x01 => Ok(ClientMessages::Control {
-                self_port: u16::from_be_bytes(value[1..3].try_into().unwrap()),
-                secret: u128::from_be_bytes(value[3..19].try_into().expect("u128 is 16 bytes")),
-                tracks_acceleration_target: [
-                    f32::from_be_bytes(value[19..23].try_into().expect("f32 is 4 bytes")),
-                    f32::from_be_bytes(value[23..27].try_into().expect("f32 is 4 bytes")),
-                ],
-                turret_acceleration_target: f32::from_be_bytes(
-                    value[27..31].try_into().expect("f32 is 4 bytes"),
-                ),
-                shoot: value[31] != 0,
+                self_port: reader.u16()?,
+                secret: reader.u128()?,
+                tracks_acceleration_target: [reader.f32()?, reader.f32()?],
+                turret_acceleration_target: reader.f32()?,
+                shoot: reader.u8()? != 0,
             }),
-            _ => Err(()),
+            tag => Err(DecodeError::UnknownTag(tag)),
         }
     }
 }
@@ -115,10 +131,23 @@ mod tests {
     fn test_client() {
         let cm = ClientMessages::Connect {
             self_port: (UNIX_EPOCH.elapsed().unwrap().as_secs() % (u16::MAX as u64)) as u16,
+            team: None,
         };
         println!("{:?} -> {:?}", cm, Vec::from(&cm));
-        assert_eq!(Vec::from(&cm).len(), 3);
+        assert_eq!(Vec::from(&cm).len(), 4);
         assert_eq!(ClientMessages::try_from(&Vec::from(&cm)[..]).unwrap(), cm);
+        let cm = ClientMessages::Connect {
+            self_port: rand::random(),
+            team: Some(1),
+        };
+        assert_eq!(ClientMessages::try_from(&Vec::from(&cm)[..]).unwrap(), cm);
+        assert_eq!(
+            ClientMessages::try_from(&[0x00, 0x0f, 0xa1][..]).unwrap(),
+            ClientMessages::Connect {
+                self_port: 0x0fa1,
+                team: None
+            }
+        );
         let cm = ClientMessages::Control {
             self_port: rand::random(),
             secret: rand::random(),
@@ -130,4 +159,41 @@ mod tests {
         assert_eq!(Vec::from(&cm).len(), 32);
         assert_eq!(ClientMessages::try_from(&Vec::from(&cm)[..]).unwrap(), cm);
     }
+
+    #[test]
+    fn test_truncated() {
+        let cm = ClientMessages::Control {
+            self_port: rand::random(),
+            secret: rand::random(),
+            tracks_acceleration_target: [rand::random(), rand::random()],
+            turret_acceleration_target: rand::random(),
+            shoot: false,
+        };
+        let encoded = Vec::from(&cm);
+        for len in 0..encoded.len() {
+            assert!(matches!(
+                ClientMessages::try_from(&encoded[..len]),
+                Err(DecodeError::Truncated { got, .. }) if got == len
+            ));
+        }
+        assert_eq!(
+            ClientMessages::try_from(&[0x00, 0x0f][..]),
+            Err(DecodeError::Truncated {
+                expected: 3,
+                got: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_random_buffers() {
+        for _ in 0..10_000 {
+            let mut buf = vec![0; rand::random_range(0..64)];
+            rand::fill(&mut buf[..]);
+            if let Some(tag) = buf.first_mut() {
+                *tag %= 3;
+            }
+            let _ = ClientMessages::try_from(&buf[..]);
+        }
+    }
 }
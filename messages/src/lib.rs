@@ -1,2 +1,107 @@
+use std::{error::Error, fmt::Display};
+
 pub mod client;
 pub mod server;
+
+/// Version of the protocol, sent to the clients in [`server::ServerMessages::Welcome`].
+pub const PROTOCOL_VERSION: u8 = 6;
+
+/// Reasons why a received buffer could not be decoded into a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The first byte does not correspond to any known message.
+    UnknownTag(u8),
+    /// The buffer ended before the whole message could be read.
+    Truncated { expected: usize, got: usize },
+    /// A list in the message holds more elements than the protocol allows.
+    CountTooLarge { count: usize, max: usize },
+    /// A versioned payload uses a version this library does not understand.
+    UnknownVersion(u8),
+    /// A disconnect message holds an unknown reason code.
+    UnknownReason(u8),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnknownTag(tag) => write!(f, "unknown message tag {tag:#04x}"),
+            DecodeError::Truncated { expected, got } => {
+                write!(f, "message truncated: expected {expected} bytes, got {got}")
+            }
+            DecodeError::CountTooLarge { count, max } => {
+                write!(
+                    f,
+                    "message holds {count} elements, at most {max} are allowed"
+                )
+            }
+            DecodeError::UnknownVersion(version) => write!(f, "unknown payload version {version}"),
+            DecodeError::UnknownReason(reason) => write!(f, "unknown disconnect reason {reason}"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Bounds-checked cursor over a received buffer.
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Make sure that `len` more bytes can be read.
+    pub(crate) fn require(&self, len: usize) -> Result<(), DecodeError> {
+        let expected = self.pos + len;
+        if expected <= self.buf.len() {
+            Ok(())
+        } else {
+            Err(DecodeError::Truncated {
+                expected,
+                got: self.buf.len(),
+            })
+        }
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<&'a [u8; N], DecodeError> {
+        self.require(N)?;
+        let bytes = self.buf[self.pos..self.pos + N]
+            .try_into()
+            .expect("length was checked");
+        self.pos += N;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, DecodeError> {
+        self.array::<1>().map(|b| b[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, DecodeError> {
+        self.array().map(|b| u16::from_be_bytes(*b))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, DecodeError> {
+        self.array().map(|b| u32::from_be_bytes(*b))
+    }
+
+    pub(crate) fn u128(&mut self) -> Result<u128, DecodeError> {
+        self.array().map(|b| u128::from_be_bytes(*b))
+    }
+
+    pub(crate) fn f32(&mut self) -> Result<f32, DecodeError> {
+        self.array().map(|b| f32::from_be_bytes(*b))
+    }
+
+    /// Read a one byte element count, and check that `count * size` bytes follow it.
+    pub(crate) fn count(&mut self, size: usize, max: usize) -> Result<usize, DecodeError> {
+        let count = usize::from(self.u8()?);
+        if count > max {
+            return Err(DecodeError::CountTooLarge { count, max });
+        }
+        self.require(count * size)?;
+        Ok(count)
+    }
+}
@@ -1,13 +1,34 @@
+use crate::{DecodeError, Reader};
+
+/// Version of the [`Map`] payload in a [`ServerMessages::MapChange`].
+pub const MAP_FORMAT_VERSION: u8 = 1;
+/// Maximum length of a map name in bytes.
+pub const MAX_NAME_LENGTH: usize = u8::MAX as usize;
+/// Maximum number of walls in a [`ServerMessages::MapChange`].
+pub const MAX_WALLS: usize = u8::MAX as usize;
+/// Maximum number of spawn points in a [`ServerMessages::MapChange`].
+pub const MAX_SPAWNS: usize = u8::MAX as usize;
+/// Size of the largest possible [`ServerMessages`], a [`ServerMessages::MapChange`] at capacity.
+pub const MAX_MESSAGE_SIZE: usize =
+    1 + 16 + 2 + MAX_NAME_LENGTH + 16 + 1 + MAX_WALLS * 24 + 1 + MAX_SPAWNS * 8;
+/// Maximum number of tanks in a [`ServerMessages::State`].
+pub const MAX_TANKS: usize = 32;
+/// Maximum number of bullets in a [`ServerMessages::State`].
+pub const MAX_BULLETS: usize = u8::MAX as usize;
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Wall {
     pub origin: [f32; 2],
     pub direction_length: [f32; 2],
+    /// Unit normal of the wall, pointing to the side bullets bounce off of.
+    pub normal: [f32; 2],
 }
 
 impl From<&Wall> for Vec<u8> {
     fn from(value: &Wall) -> Self {
         let mut vec = value.origin.to_vec();
         vec.append(&mut value.direction_length.to_vec());
+        vec.append(&mut value.normal.to_vec());
         vec.iter_mut()
             .map(|f| f.to_be_bytes())
             .collect::<Vec<_>>()
@@ -15,8 +36,8 @@ impl From<&Wall> for Vec<u8> {
     }
 }
 
-impl From<&[u8; 16]> for Wall {
-    fn from(value: &[u8; 16]) -> Self {
+impl From<&[u8; 24]> for Wall {
+    fn from(value: &[u8; 24]) -> Self {
         Wall {
             origin: [
                 f32::from_be_bytes(value[0..4].try_into().unwrap()),
@@ -26,15 +47,130 @@ impl From<&[u8; 16]> for Wall {
                 f32::from_be_bytes(value[8..12].try_into().unwrap()),
                 f32::from_be_bytes(value[12..16].try_into().unwrap()),
             ],
+            normal: [
+                f32::from_be_bytes(value[16..20].try_into().unwrap()),
+                f32::from_be_bytes(value[20..24].try_into().unwrap()),
+            ],
+        }
+    }
+}
+
+/// Axis-aligned bounding box of a map.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bounds {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl From<&Bounds> for Vec<u8> {
+    fn from(value: &Bounds) -> Self {
+        [value.min, value.max]
+            .concat()
+            .iter()
+            .map(|f| f.to_be_bytes())
+            .collect::<Vec<_>>()
+            .concat()
+    }
+}
+
+impl From<&[u8; 16]> for Bounds {
+    fn from(value: &[u8; 16]) -> Self {
+        let f = |i: usize| f32::from_be_bytes(value[i..i + 4].try_into().unwrap());
+        Bounds {
+            min: [f(0), f(4)],
+            max: [f(8), f(12)],
         }
     }
 }
 
+/// Everything a client needs to know about the current map.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Map {
+    /// Human readable name, at most [`MAX_NAME_LENGTH`] bytes are sent.
+    pub name: String,
+    pub bounds: Bounds,
+    pub walls: Vec<Wall>,
+    pub spawns: Vec<[f32; 2]>,
+}
+
+impl From<&Map> for Vec<u8> {
+    fn from(value: &Map) -> Self {
+        let mut name = value.name.as_bytes();
+        if name.len() > MAX_NAME_LENGTH {
+            let mut end = MAX_NAME_LENGTH;
+            while !value.name.is_char_boundary(end) {
+                end -= 1;
+            }
+            name = &name[..end];
+        }
+        let mut vec = vec![MAP_FORMAT_VERSION, name.len() as u8];
+        vec.extend_from_slice(name);
+        vec.append(&mut Vec::from(&value.bounds));
+        vec.push(value.walls.len() as u8);
+        value
+            .walls
+            .iter()
+            .for_each(|w| vec.append(&mut Vec::from(w)));
+        vec.push(value.spawns.len() as u8);
+        value
+            .spawns
+            .iter()
+            .flatten()
+            .for_each(|f| vec.extend_from_slice(&f.to_be_bytes()));
+        vec
+    }
+}
+
+impl Map {
+    fn read(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let version = reader.u8()?;
+        if version != MAP_FORMAT_VERSION {
+            return Err(DecodeError::UnknownVersion(version));
+        }
+        let name_length = reader.count(1, MAX_NAME_LENGTH)?;
+        let mut name = vec![];
+        for _ in 0..name_length {
+            name.push(reader.u8()?);
+        }
+        let bounds = Bounds::from(reader.array()?);
+        let wall_count = reader.count(24, MAX_WALLS)?;
+        let mut walls = vec![];
+        for _ in 0..wall_count {
+            walls.push(Wall::from(reader.array()?))
+        }
+        let spawn_count = reader.count(8, MAX_SPAWNS)?;
+        let mut spawns = vec![];
+        for _ in 0..spawn_count {
+            spawns.push([reader.f32()?, reader.f32()?])
+        }
+        Ok(Map {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            bounds,
+            walls,
+            spawns,
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for Map {
+    type Error = DecodeError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Map::read(&mut Reader::new(value))
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Tank {
+    /// Id of the player owning the tank.
+    pub id: u32,
+    /// Team of the player owning the tank, `None` when playing free-for-all.
+    pub team: Option<u8>,
     pub position: [f32; 2],
     pub tank_direction: [f32; 2],
     pub turret_direction: [f32; 2],
+    /// Hit points left, the tank is destroyed when they reach `0`.
+    pub health: f32,
 }
 
 impl From<&Tank> for Vec<u8> {
@@ -42,28 +178,36 @@ impl From<&Tank> for Vec<u8> {
         let mut vec = value.position.to_vec();
         vec.append(&mut value.tank_direction.to_vec());
         vec.append(&mut value.turret_direction.to_vec());
-        vec.iter_mut()
+        vec.push(value.health);
+        let mut vec = vec
+            .iter_mut()
             .map(|f| f.to_be_bytes())
             .collect::<Vec<_>>()
-            .concat()
+            .concat();
+        vec.insert(0, value.team.unwrap_or(u8::MAX));
+        vec.splice(0..0, value.id.to_be_bytes());
+        vec
     }
 }
 
-impl From<&[u8; 24]> for Tank {
-    fn from(value: &[u8; 24]) -> Self {
+impl From<&[u8; 33]> for Tank {
+    fn from(value: &[u8; 33]) -> Self {
         Tank {
+            id: u32::from_be_bytes(value[0..4].try_into().unwrap()),
+            team: Some(value[4]).filter(|&team| team != u8::MAX),
             position: [
-                f32::from_be_bytes(value[0..4].try_into().unwrap()),
-                f32::from_be_bytes(value[4..8].try_into().unwrap()),
+                f32::from_be_bytes(value[5..9].try_into().unwrap()),
+                f32::from_be_bytes(value[9..13].try_into().unwrap()),
             ],
             tank_direction: [
-                f32::from_be_bytes(value[8..12].try_into().unwrap()),
-                f32::from_be_bytes(value[12..16].try_into().unwrap()),
+                f32::from_be_bytes(value[13..17].try_into().unwrap()),
+                f32::from_be_bytes(value[17..21].try_into().unwrap()),
             ],
             turret_direction: [
-                f32::from_be_bytes(value[16..20].try_into().unwrap()),
-                f32::from_be_bytes(value[20..24].try_into().unwrap()),
+                f32::from_be_bytes(value[21..25].try_into().unwrap()),
+                f32::from_be_bytes(value[25..29].try_into().unwrap()),
             ],
+            health: f32::from_be_bytes(value[29..33].try_into().unwrap()),
         }
     }
 }
@@ -100,22 +244,117 @@ impl From<&[u8; 16]> for Bullet {
     }
 }
 
+/// Physical limits of a player's tank, as configured on the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Parameters {
+    pub tank_radius: f32,
+    pub track_max_velocity: [f32; 2],
+    pub track_max_acceleration: [f32; 2],
+    pub turret_max_velocity: f32,
+    pub turret_max_acceleration: f32,
+    /// Delay between two shots (in seconds).
+    pub shoot_delay: f32,
+    pub bullet_radius: f32,
+    pub bullet_speed: f32,
+    pub bullet_max_bounces: i8,
+    /// Hit points of a freshly spawned tank.
+    pub max_health: f32,
+}
+
+impl From<&Parameters> for Vec<u8> {
+    fn from(value: &Parameters) -> Self {
+        let mut vec = vec![value.tank_radius];
+        vec.append(&mut value.track_max_velocity.to_vec());
+        vec.append(&mut value.track_max_acceleration.to_vec());
+        vec.push(value.turret_max_velocity);
+        vec.push(value.turret_max_acceleration);
+        vec.push(value.shoot_delay);
+        vec.push(value.bullet_radius);
+        vec.push(value.bullet_speed);
+        let mut vec = vec
+            .iter_mut()
+            .map(|f| f.to_be_bytes())
+            .collect::<Vec<_>>()
+            .concat();
+        vec.push(value.bullet_max_bounces as u8);
+        vec.extend(value.max_health.to_be_bytes());
+        vec
+    }
+}
+
+impl From<&[u8; 45]> for Parameters {
+    fn from(value: &[u8; 45]) -> Self {
+        let f = |i: usize| f32::from_be_bytes(value[i..i + 4].try_into().unwrap());
+        Parameters {
+            tank_radius: f(0),
+            track_max_velocity: [f(4), f(8)],
+            track_max_acceleration: [f(12), f(16)],
+            turret_max_velocity: f(20),
+            turret_max_acceleration: f(24),
+            shoot_delay: f(28),
+            bullet_radius: f(32),
+            bullet_speed: f(36),
+            bullet_max_bounces: value[40] as i8,
+            max_health: f(41),
+        }
+    }
+}
+
+/// Why the server ended a player's session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The player did not send a message within the inactivity timeout.
+    Inactivity = 0,
+    /// The player was removed by the server operator.
+    Kicked = 1,
+    /// The server is shutting down.
+    ServerShutdown = 2,
+    /// The server already holds the maximum number of players.
+    ServerFull = 3,
+}
+
+impl TryFrom<u8> for DisconnectReason {
+    type Error = DecodeError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Inactivity),
+            1 => Ok(Self::Kicked),
+            2 => Ok(Self::ServerShutdown),
+            3 => Ok(Self::ServerFull),
+            reason => Err(DecodeError::UnknownReason(reason)),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ServerMessages {
     MapChange {
         // + 1 byte
-        secret: u128,     // 16 bytes
-        walls: Vec<Wall>, // 256 * 16 bytes
+        secret: u128, // 16 bytes
+        map: Map,     // 2 + 255 + 16 + 255 * 24 + 1 + 255 * 8 + 1 bytes
     },
     State {
         // + 1 byte
         secret: u128,         // 16 bytes
-        tanks: Vec<Tank>,     // 32 * 24 + 1 bytes
+        player_id: u32,       // 4 bytes
+        own_tank: Option<u8>, // 1 byte, index into `tanks`
+        tanks: Vec<Tank>,     // 32 * 33 + 1 bytes
         bullets: Vec<Bullet>, // 256 * 16 + 1 bytes
     },
-    Disconnected,
+    Disconnected {
+        // + 1 byte
+        reason: DisconnectReason, // 1 byte
+    },
+    Welcome {
+        // + 1 byte
+        version: u8,            // 1 byte
+        player_id: u32,         // 4 bytes
+        secret: u128,           // 16 bytes
+        parameters: Parameters, // 45 bytes
+    },
 }
-// total of up to: 4883 bytes
+// total of up to: 8452 bytes
 
 impl ServerMessages {
     pub fn to_vec(&self) -> Vec<u8> {
@@ -124,13 +363,29 @@ impl ServerMessages {
 
     pub fn change_secret(&mut self, new_secret: u128) {
         match self {
-            ServerMessages::MapChange { secret, walls: _ }
-            | ServerMessages::State {
-                secret,
-                tanks: _,
-                bullets: _,
-            } => *secret = new_secret,
-            ServerMessages::Disconnected => todo!(),
+            ServerMessages::MapChange { secret, .. }
+            | ServerMessages::State { secret, .. }
+            | ServerMessages::Welcome { secret, .. } => *secret = new_secret,
+            ServerMessages::Disconnected { .. } => {}
+        }
+    }
+
+    /// Address a [`ServerMessages::State`] to the player with the given id.
+    ///
+    /// This also looks up the index of the player's own tank.
+    pub fn change_player(&mut self, new_player_id: u32) {
+        if let ServerMessages::State {
+            player_id,
+            own_tank,
+            tanks,
+            ..
+        } = self
+        {
+            *player_id = new_player_id;
+            *own_tank = tanks
+                .iter()
+                .position(|tank| tank.id == new_player_id)
+                .map(|index| index as u8);
         }
     }
 }
@@ -138,75 +393,92 @@ impl ServerMessages {
 impl From<&ServerMessages> for Vec<u8> {
     fn from(value: &ServerMessages) -> Self {
         match value {
-            ServerMessages::MapChange { secret, walls } => {
+            ServerMessages::MapChange { secret, map } => {
                 let mut vec = vec![0x00];
                 vec.append(&mut secret.to_be_bytes().to_vec());
-                vec.push(walls.len() as u8);
-                walls.iter().for_each(|w| vec.append(&mut Vec::from(w)));
+                vec.append(&mut Vec::from(map));
                 vec
             }
             ServerMessages::State {
                 secret,
+                player_id,
+                own_tank,
                 tanks,
                 bullets,
             } => {
                 let mut vec = vec![0x01];
                 vec.append(&mut secret.to_be_bytes().to_vec());
+                vec.append(&mut player_id.to_be_bytes().to_vec());
+                // only the first MAX_TANKS tanks and MAX_BULLETS bullets fit in the message
+                let tanks = &tanks[..tanks.len().min(MAX_TANKS)];
+                let bullets = &bullets[..bullets.len().min(MAX_BULLETS)];
+                let own_tank = own_tank.filter(|&index| usize::from(index) < tanks.len());
+                vec.push(own_tank.unwrap_or(u8::MAX));
                 vec.push(tanks.len() as u8);
                 tanks.iter().for_each(|t| vec.append(&mut Vec::from(t)));
                 vec.push(bullets.len() as u8);
                 bullets.iter().for_each(|b| vec.append(&mut Vec::from(b)));
                 vec
             }
-            ServerMessages::Disconnected => vec![0x02],
+            ServerMessages::Disconnected { reason } => vec![0x02, *reason as u8],
+            ServerMessages::Welcome {
+                version,
+                player_id,
+                secret,
+                parameters,
+            } => {
+                let mut vec = vec![0x03, *version];
+                vec.append(&mut player_id.to_be_bytes().to_vec());
+                vec.append(&mut secret.to_be_bytes().to_vec());
+                vec.append(&mut Vec::from(parameters));
+                vec
+            }
         }
     }
 }
 
 impl TryFrom<&[u8]> for ServerMessages {
-    type Error = ();
+    type Error = DecodeError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        match value[0] {
-            0x00 => {
-                let secret = u128::from_be_bytes(value[1..17].try_into().unwrap());
-                let wall_count = value[17] as usize;
-                let mut walls = vec![];
-                for i in 0..wall_count {
-                    walls.push(Wall::from(
-                        &value[18 + i * 16..34 + i * 16].try_into().unwrap(),
-                    ))
-                }
-                Ok(Self::MapChange { secret, walls })
-            }
+        let mut reader = Reader::new(value);
+        match reader.u8()? {
+            0x00 => Ok(Self::MapChange {
+                secret: reader.u128()?,
+                map: Map::read(&mut reader)?,
+            }),
             0x01 => {
-                let secret = u128::from_be_bytes(value[1..17].try_into().unwrap());
-                let tank_count = value[17] as usize;
+                let secret = reader.u128()?;
+                let player_id = reader.u32()?;
+                let own_tank = Some(reader.u8()?).filter(|&index| index != u8::MAX);
+                let tank_count = reader.count(33, MAX_TANKS)?;
                 let mut tanks = vec![];
-                for i in 0..tank_count {
-                    tanks.push(Tank::from(
-                        &value[18 + i * 24..42 + i * 24].try_into().unwrap(),
-                    ))
+                for _ in 0..tank_count {
+                    tanks.push(Tank::from(reader.array()?))
                 }
-                let start = 18 + tank_count * 24;
-                let bullet_count = value[start] as usize;
-                let start = start + 1;
+                let bullet_count = reader.count(16, MAX_BULLETS)?;
                 let mut bullets = vec![];
-                for i in 0..bullet_count {
-                    bullets.push(Bullet::from(
-                        &value[start + i * 16..start + (i + 1) * 16]
-                            .try_into()
-                            .unwrap(),
-                    ))
+                for _ in 0..bullet_count {
+                    bullets.push(Bullet::from(reader.array()?))
                 }
                 Ok(Self::State {
                     secret,
+                    player_id,
+                    own_tank,
                     tanks,
                     bullets,
                 })
             }
-            0x02 => Ok(Self::Disconnected),
-            _ => Err(()),
+            0x02 => Ok(Self::Disconnected {
+                reason: DisconnectReason::try_from(reader.u8()?)?,
+            }),
+            0x03 => Ok(Self::Welcome {
+                version: reader.u8()?,
+                player_id: reader.u32()?,
+                secret: reader.u128()?,
+                parameters: Parameters::from(reader.array()?),
+            }),
+            tag => Err(DecodeError::UnknownTag(tag)),
         }
     }
 }
@@ -218,32 +490,53 @@ mod tests {
     #[test]
     fn test_walls() {
         let wall_count = rand::random_range(0..32);
-        let mut walls = vec![];
-        for _ in 0..wall_count {
-            walls.push(Wall {
-                origin: [rand::random(), rand::random()],
-                direction_length: [rand::random(), rand::random()],
-            })
-        }
+        let spawn_count = rand::random_range(0..8);
         let map = ServerMessages::MapChange {
             secret: rand::random(),
-            walls,
+            map: Map {
+                name: "Layout-00".to_owned(),
+                bounds: Bounds {
+                    min: [rand::random(), rand::random()],
+                    max: [rand::random(), rand::random()],
+                },
+                walls: (0..wall_count)
+                    .map(|_| Wall {
+                        origin: [rand::random(), rand::random()],
+                        direction_length: [rand::random(), rand::random()],
+                        normal: [rand::random(), rand::random()],
+                    })
+                    .collect(),
+                spawns: (0..spawn_count)
+                    .map(|_| [rand::random(), rand::random()])
+                    .collect(),
+            },
         };
-        assert_eq!(Vec::from(&map).len(), 18 + wall_count * 16);
+        assert_eq!(
+            Vec::from(&map).len(),
+            19 + 9 + 16 + 1 + wall_count * 24 + 1 + spawn_count * 8
+        );
         assert_eq!(ServerMessages::try_from(&Vec::from(&map)[..]).unwrap(), map);
+        if let ServerMessages::MapChange { map, .. } = map {
+            assert_eq!(Map::try_from(&Vec::from(&map)[..]).unwrap(), map);
+        }
     }
 
     #[test]
     fn test_status() {
         let tank_count = rand::random_range(0..8);
         let bullet_count = rand::random_range(0..32);
-        let state = ServerMessages::State {
+        let mut state = ServerMessages::State {
             secret: rand::random(),
+            player_id: 0,
+            own_tank: None,
             tanks: (0..tank_count)
-                .map(|_| Tank {
+                .map(|id| Tank {
+                    id,
+                    team: (id % 2 == 0).then_some(id as u8 / 2),
                     position: [rand::random(), rand::random()],
                     tank_direction: [rand::random(), rand::random()],
                     turret_direction: [rand::random(), rand::random()],
+                    health: rand::random(),
                 })
                 .collect(),
             bullets: (0..bullet_count)
@@ -254,16 +547,26 @@ mod tests {
                 .collect(),
         };
 
+        let player_id = rand::random_range(0..8);
+        state.change_player(player_id);
+        match &state {
+            ServerMessages::State { own_tank, .. } => assert_eq!(
+                *own_tank,
+                (player_id < tank_count).then_some(player_id as u8)
+            ),
+            _ => panic!("Something is very wrong"),
+        }
+
         assert_eq!(
             Vec::from(&state).len(),
-            19 + tank_count * 24 + bullet_count * 16
+            24 + tank_count as usize * 33 + bullet_count * 16
         );
         match ServerMessages::try_from(&Vec::from(&state)[..]).unwrap() {
             ServerMessages::State { tanks, bullets, .. } => {
-                assert_eq!(tanks.len(), tank_count);
+                assert_eq!(tanks.len(), tank_count as usize);
                 assert_eq!(bullets.len(), bullet_count);
             }
-            _ => assert!(false, "Something is very wrong"),
+            _ => panic!("Something is very wrong"),
         }
         assert_eq!(
             ServerMessages::try_from(&Vec::from(&state)[..]).unwrap(),
@@ -271,11 +574,177 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_welcome() {
+        let welcome = ServerMessages::Welcome {
+            version: crate::PROTOCOL_VERSION,
+            player_id: rand::random(),
+            secret: rand::random(),
+            parameters: Parameters {
+                tank_radius: rand::random(),
+                track_max_velocity: [rand::random(), rand::random()],
+                track_max_acceleration: [rand::random(), rand::random()],
+                turret_max_velocity: rand::random(),
+                turret_max_acceleration: rand::random(),
+                shoot_delay: rand::random(),
+                bullet_radius: rand::random(),
+                bullet_speed: rand::random(),
+                bullet_max_bounces: rand::random(),
+                max_health: rand::random(),
+            },
+        };
+        assert_eq!(Vec::from(&welcome).len(), 67);
+        assert_eq!(
+            ServerMessages::try_from(&Vec::from(&welcome)[..]).unwrap(),
+            welcome
+        );
+    }
+
+    fn random_state() -> ServerMessages {
+        ServerMessages::State {
+            secret: rand::random(),
+            player_id: rand::random(),
+            own_tank: Some(0),
+            tanks: (0..rand::random_range(1..8))
+                .map(|_| Tank {
+                    id: rand::random(),
+                    team: Some(rand::random_range(0..8)),
+                    position: [rand::random(), rand::random()],
+                    tank_direction: [rand::random(), rand::random()],
+                    turret_direction: [rand::random(), rand::random()],
+                    health: rand::random(),
+                })
+                .collect(),
+            bullets: (0..rand::random_range(1..32))
+                .map(|_| Bullet {
+                    position: [rand::random(), rand::random()],
+                    direction: [rand::random(), rand::random()],
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let encoded = Vec::from(&random_state());
+        for len in 0..encoded.len() {
+            match ServerMessages::try_from(&encoded[..len]) {
+                Err(DecodeError::Truncated { expected, got }) => {
+                    assert_eq!(got, len);
+                    assert!(expected > len);
+                }
+                other => panic!("decoding {len} bytes gave {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_too_many_tanks() {
+        let mut encoded = Vec::from(&random_state());
+        encoded[22] = MAX_TANKS as u8 + 1;
+        assert_eq!(
+            ServerMessages::try_from(&encoded[..]),
+            Err(DecodeError::CountTooLarge {
+                count: MAX_TANKS + 1,
+                max: MAX_TANKS
+            })
+        );
+    }
+
+    #[test]
+    fn test_oversized_state() {
+        let ServerMessages::State {
+            secret,
+            player_id,
+            tanks,
+            bullets,
+            ..
+        } = random_state()
+        else {
+            unreachable!()
+        };
+        let state = ServerMessages::State {
+            secret,
+            player_id,
+            own_tank: Some(MAX_TANKS as u8 + 3),
+            tanks: tanks.into_iter().cycle().take(MAX_TANKS + 5).collect(),
+            bullets: bullets.into_iter().cycle().take(MAX_BULLETS + 5).collect(),
+        };
+        match ServerMessages::try_from(&Vec::from(&state)[..]).unwrap() {
+            ServerMessages::State {
+                own_tank,
+                tanks,
+                bullets,
+                ..
+            } => {
+                assert_eq!(own_tank, None);
+                assert_eq!(tanks.len(), MAX_TANKS);
+                assert_eq!(bullets.len(), MAX_BULLETS);
+            }
+            _ => panic!("Something is very wrong"),
+        }
+    }
+
+    #[test]
+    fn test_map_version() {
+        let mut encoded = Vec::from(&ServerMessages::MapChange {
+            secret: rand::random(),
+            map: Map::default(),
+        });
+        encoded[17] = MAP_FORMAT_VERSION + 1;
+        assert_eq!(
+            ServerMessages::try_from(&encoded[..]),
+            Err(DecodeError::UnknownVersion(MAP_FORMAT_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn test_long_name() {
+        let map = Map {
+            name: "é".repeat(200),
+            ..Default::default()
+        };
+        let encoded = Vec::from(&ServerMessages::MapChange { secret: 0, map });
+        match ServerMessages::try_from(&encoded[..]).unwrap() {
+            ServerMessages::MapChange { map, .. } => assert_eq!(map.name, "é".repeat(127)),
+            _ => panic!("Something is very wrong"),
+        }
+    }
+
+    #[test]
+    fn test_unknown_tag() {
+        assert_eq!(
+            ServerMessages::try_from(&[0xff][..]),
+            Err(DecodeError::UnknownTag(0xff))
+        );
+    }
+
+    #[test]
+    fn test_random_buffers() {
+        for _ in 0..10_000 {
+            let mut buf = vec![0; rand::random_range(0..512)];
+            rand::fill(&mut buf[..]);
+            if let Some(tag) = buf.first_mut() {
+                *tag %= 4;
+            }
+            let _ = ServerMessages::try_from(&buf[..]);
+        }
+    }
+
     #[test]
     fn test_disconnect() {
+        for reason in [
+            DisconnectReason::Inactivity,
+            DisconnectReason::Kicked,
+            DisconnectReason::ServerShutdown,
+            DisconnectReason::ServerFull,
+        ] {
+            let msg = ServerMessages::Disconnected { reason };
+            assert_eq!(ServerMessages::try_from(&Vec::from(&msg)[..]).unwrap(), msg);
+        }
         assert_eq!(
-            ServerMessages::try_from(&Vec::from(&ServerMessages::Disconnected)[..]).unwrap(),
-            ServerMessages::Disconnected
+            ServerMessages::try_from(&[0x02, 0xff][..]),
+            Err(DecodeError::UnknownReason(0xff))
         );
     }
 }
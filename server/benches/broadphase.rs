@@ -0,0 +1,169 @@
+//! Compares the uniform grid of the broadphase with testing every pair.
+//! Run with `cargo bench --bench broadphase`.
+
+use std::hint::black_box;
+
+use bevy::math::Vec2;
+use criterion::{Criterion, criterion_group, criterion_main};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/broadphase.rs"]
+mod broadphase;
+
+use broadphase::{CELL_SIZE, Grid};
+
+const BULLETS: usize = 128;
+const BULLET_RADIUS: f32 = 5.0;
+const WALLS: usize = 400;
+const TANKS: usize = 8;
+const TANK_RADIUS: f32 = 12.0;
+const PHYSICS_STEPS: usize = 64;
+
+/// A random point on a large map.
+fn random_point(rng: &mut StdRng) -> Vec2 {
+    Vec2::new(
+        rng.random_range(-800.0..800.0),
+        rng.random_range(-700.0..700.0),
+    )
+}
+
+/// Random short walls, as their two endpoints.
+fn random_walls(rng: &mut StdRng) -> Vec<(Vec2, Vec2)> {
+    (0..WALLS)
+        .map(|_| {
+            let start = random_point(rng);
+            let end =
+                start + Vec2::new(rng.random_range(-60.0..60.0), rng.random_range(-60.0..60.0));
+            (start, end)
+        })
+        .collect()
+}
+
+/// Distance from `point` to the segment from `start` to `end`.
+fn segment_distance(point: Vec2, (start, end): (Vec2, Vec2)) -> f32 {
+    let direction = end - start;
+    let along = ((point - start).dot(direction) / direction.length_squared()).clamp(0.0, 1.0);
+    point.distance(start + direction * along)
+}
+
+fn bullet_pairs(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let bullets: Vec<Vec2> = (0..BULLETS).map(|_| random_point(&mut rng)).collect();
+
+    c.bench_function("bullet pairs / every pair", |b| {
+        b.iter(|| {
+            let mut hits = 0;
+            for (i, first) in bullets.iter().enumerate() {
+                for second in &bullets[i + 1..] {
+                    if first.distance(*second) < 2.0 * BULLET_RADIUS {
+                        hits += 1;
+                    }
+                }
+            }
+            black_box(hits)
+        })
+    });
+
+    let mut grid = Grid::new(CELL_SIZE);
+    c.bench_function("bullet pairs / grid", |b| {
+        b.iter(|| {
+            grid.clear();
+            for (i, &bullet) in bullets.iter().enumerate() {
+                let extent = Vec2::splat(BULLET_RADIUS);
+                grid.insert(i, bullet - extent, bullet + extent);
+            }
+            let hits = grid
+                .pairs()
+                .into_iter()
+                .filter(|&(first, second)| {
+                    bullets[first].distance(bullets[second]) < 2.0 * BULLET_RADIUS
+                })
+                .count();
+            black_box(hits)
+        })
+    });
+}
+
+fn tank_walls(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let walls = random_walls(&mut rng);
+    let tanks: Vec<Vec2> = (0..TANKS).map(|_| random_point(&mut rng)).collect();
+
+    c.bench_function("tank walls / every wall", |b| {
+        b.iter(|| {
+            let mut contacts = 0;
+            for _ in 0..PHYSICS_STEPS {
+                for &tank in &tanks {
+                    contacts += walls
+                        .iter()
+                        .filter(|&&wall| segment_distance(tank, wall) < TANK_RADIUS)
+                        .count();
+                }
+            }
+            black_box(contacts)
+        })
+    });
+
+    let mut grid = Grid::new(CELL_SIZE);
+    for (i, &(start, end)) in walls.iter().enumerate() {
+        grid.insert(i, start.min(end), start.max(end));
+    }
+    c.bench_function("tank walls / grid", |b| {
+        b.iter(|| {
+            let mut contacts = 0;
+            for _ in 0..PHYSICS_STEPS {
+                for &tank in &tanks {
+                    let extent = Vec2::splat(TANK_RADIUS);
+                    contacts += grid
+                        .query(tank - extent, tank + extent)
+                        .into_iter()
+                        .filter(|&wall| segment_distance(tank, walls[wall]) < TANK_RADIUS)
+                        .count();
+                }
+            }
+            black_box(contacts)
+        })
+    });
+}
+
+fn bullet_walls(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let walls = random_walls(&mut rng);
+    let bullets: Vec<Vec2> = (0..BULLETS).map(|_| random_point(&mut rng)).collect();
+
+    c.bench_function("bullet walls / every wall", |b| {
+        b.iter(|| {
+            let mut contacts = 0;
+            for &bullet in &bullets {
+                contacts += walls
+                    .iter()
+                    .filter(|&&wall| segment_distance(bullet, wall) < BULLET_RADIUS)
+                    .count();
+            }
+            black_box(contacts)
+        })
+    });
+
+    let mut grid = Grid::new(CELL_SIZE);
+    for (i, &(start, end)) in walls.iter().enumerate() {
+        grid.insert(i, start.min(end), start.max(end));
+    }
+    c.bench_function("bullet walls / grid", |b| {
+        b.iter(|| {
+            let mut contacts = 0;
+            for &bullet in &bullets {
+                let extent = Vec2::splat(BULLET_RADIUS);
+                contacts += grid
+                    .query(bullet - extent, bullet + extent)
+                    .into_iter()
+                    .filter(|&wall| segment_distance(bullet, walls[wall]) < BULLET_RADIUS)
+                    .count();
+            }
+            black_box(contacts)
+        })
+    });
+}
+
+criterion_group!(benches, bullet_pairs, tank_walls, bullet_walls);
+criterion_main!(benches);
@@ -0,0 +1,143 @@
+use bevy::{
+    ecs::{entity::Entity, resource::Resource},
+    math::{IVec2, Vec2},
+    platform::collections::HashMap,
+};
+
+/// Side length of the cells of the [`Broadphase`] grids.
+/// A few times the size of a tank, so that most objects only touch one or two cells.
+pub const CELL_SIZE: f32 = 64.0;
+
+/// Uniform grid bucketing items by the cells their bounding box overlaps.
+/// Items that are close to each other share at least one cell, which limits the exact
+/// collision tests to a few candidates.
+pub struct Grid<T> {
+    cell_size: f32,
+    /// The items in each cell, with the first cell of their bounding box.
+    cells: HashMap<IVec2, Vec<(T, IVec2)>>,
+}
+
+impl<T: Copy> Grid<T> {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::default(),
+        }
+    }
+
+    /// Remove all items, keeping the allocated cells for the next fill.
+    pub fn clear(&mut self) {
+        self.cells.values_mut().for_each(Vec::clear);
+    }
+
+    fn cell(&self, point: Vec2) -> IVec2 {
+        (point / self.cell_size).floor().as_ivec2()
+    }
+
+    fn cells(min: IVec2, max: IVec2) -> impl Iterator<Item = IVec2> {
+        (min.x..=max.x).flat_map(move |x| (min.y..=max.y).map(move |y| IVec2::new(x, y)))
+    }
+
+    /// Add `item` with the bounding box from `min` to `max`.
+    pub fn insert(&mut self, item: T, min: Vec2, max: Vec2) {
+        let (min, max) = (self.cell(min), self.cell(max));
+        for cell in Self::cells(min, max) {
+            self.cells.entry(cell).or_default().push((item, min));
+        }
+    }
+
+    /// Items whose cells overlap the box from `min` to `max`, each one once.
+    pub fn query(&self, min: Vec2, max: Vec2) -> Vec<T> {
+        let (min, max) = (self.cell(min), self.cell(max));
+        Self::cells(min, max)
+            .filter_map(|cell| Some((cell, self.cells.get(&cell)?)))
+            .flat_map(|(cell, items)| {
+                // an item spanning several of the cells is only taken from the first of them
+                items
+                    .iter()
+                    .filter(move |(_, first)| first.max(min) == cell)
+                    .map(|&(item, _)| item)
+            })
+            .collect()
+    }
+
+    /// Pairs of items sharing a cell, each pair once.
+    pub fn pairs(&self) -> Vec<(T, T)> {
+        let mut pairs = vec![];
+        for (&cell, items) in &self.cells {
+            for (i, &(first, first_cell)) in items.iter().enumerate() {
+                for &(second, second_cell) in &items[i + 1..] {
+                    // a pair sharing several cells is only taken from the first of them
+                    if first_cell.max(second_cell) == cell {
+                        pairs.push((first, second));
+                    }
+                }
+            }
+        }
+        pairs
+    }
+}
+
+/// Grids of the walls, tanks and bullets, shared by the collision systems.
+/// The walls are rebuilt when the map changes, the tanks and bullets after they moved.
+#[derive(Resource)]
+pub struct Broadphase {
+    pub walls: Grid<Entity>,
+    /// Number of walls in the wall grid, to notice removed walls.
+    pub wall_count: usize,
+    pub tanks: Grid<Entity>,
+    pub bullets: Grid<Entity>,
+}
+
+impl Default for Broadphase {
+    fn default() -> Self {
+        Self {
+            walls: Grid::new(CELL_SIZE),
+            wall_count: 0,
+            tanks: Grid::new(CELL_SIZE),
+            bullets: Grid::new(CELL_SIZE),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let mut grid = Grid::new(10.0);
+        grid.insert(1, Vec2::new(-5.0, -5.0), Vec2::new(5.0, 5.0));
+        grid.insert(2, Vec2::new(30.0, 30.0), Vec2::new(32.0, 32.0));
+        grid.insert(3, Vec2::new(-50.0, 0.0), Vec2::new(50.0, 0.0));
+        let query = |min, max| {
+            let mut items = grid.query(Vec2::splat(min), Vec2::splat(max));
+            items.sort();
+            items
+        };
+
+        assert_eq!(query(-1.0, 1.0), [1, 3]);
+        assert_eq!(query(-20.0, 20.0), [1, 3]);
+        assert_eq!(query(25.0, 35.0), [2]);
+        assert!(query(100.0, 101.0).is_empty());
+
+        grid.clear();
+        assert!(grid.query(Vec2::splat(-1.0), Vec2::splat(1.0)).is_empty());
+    }
+
+    #[test]
+    fn test_pairs() {
+        let mut grid = Grid::new(10.0);
+        grid.insert(3, Vec2::new(-5.0, -5.0), Vec2::new(5.0, 5.0));
+        grid.insert(1, Vec2::new(-2.0, -2.0), Vec2::new(2.0, 2.0));
+        grid.insert(2, Vec2::new(40.0, 40.0), Vec2::new(42.0, 42.0));
+        grid.insert(4, Vec2::new(41.0, 0.0), Vec2::new(49.0, 0.0));
+        let mut pairs = grid
+            .pairs()
+            .into_iter()
+            .map(|(first, second)| (first.min(second), first.max(second)))
+            .collect::<Vec<_>>();
+        pairs.sort();
+        assert_eq!(pairs, [(1, 3)]);
+    }
+}
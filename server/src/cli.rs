@@ -0,0 +1,126 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+
+use crate::{
+    config::Config,
+    generator::{Generator, Symmetry},
+};
+
+/// Command line arguments of the server.
+/// Each of them can also be given as an environment variable, the command line takes precedence.
+#[derive(Parser, Debug)]
+#[command(version, about = "Server for the tank game, also displaying the game.")]
+pub struct Args {
+    /// Address to bind the UDP socket to.
+    #[arg(long, env = "BIND_ADDRESS")]
+    pub bind: Option<IpAddr>,
+    /// Port to bind the UDP socket to.
+    #[arg(long, env = "SELF_PORT")]
+    pub port: Option<u16>,
+    /// Path of the configuration file.
+    #[arg(long, env = "CONFIG", default_value = "./assets/config.jsonc")]
+    pub config: PathBuf,
+    /// Additional map files to load, next to the ones listed in the configuration.
+    #[arg(long = "map", env = "MAPS", value_delimiter = ',')]
+    pub maps: Vec<PathBuf>,
+    /// Run the simulation without opening a window, `--headless false` opens one anyway.
+    #[arg(long, env = "HEADLESS", num_args = 0..=1, default_missing_value = "true")]
+    pub headless: Option<bool>,
+    /// Seed for the random number generator.
+    #[arg(long, env = "SEED")]
+    pub seed: Option<u64>,
+    /// Record the match to this replay file.
+    #[arg(long, env = "RECORD")]
+    pub record: Option<PathBuf>,
+    /// Play back this replay file instead of hosting a match.
+    #[arg(long, env = "REPLAY", conflicts_with_all = ["headless", "record"])]
+    pub replay: Option<PathBuf>,
+    /// Edit this map file instead of hosting a match, it is created when saving if missing.
+    #[arg(long, conflicts_with_all = ["headless", "record", "replay"])]
+    pub edit: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Check map files for mistakes and exit, failing if there are errors.
+    /// The same checks run when the server starts, maps with errors are skipped.
+    ValidateMaps {
+        /// Map files to check, the configured maps if none are given.
+        paths: Vec<PathBuf>,
+    },
+    /// Generate a map and exit.
+    /// Settings that are not given are taken from the configured `map_generator`.
+    GenerateMap(GeneratorArgs),
+}
+
+/// Settings of the map generator.
+#[derive(clap::Args, Debug)]
+pub struct GeneratorArgs {
+    /// Seed of the map, a random one is chosen if absent.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Width and height of the arena.
+    #[arg(long, num_args = 2, value_names = ["WIDTH", "HEIGHT"])]
+    pub size: Option<Vec<f32>>,
+    /// Fraction of the arena covered by obstacles.
+    #[arg(long)]
+    pub density: Option<f32>,
+    #[arg(long, value_enum)]
+    pub symmetry: Option<Symmetry>,
+    /// Number of spawns in each symmetric part of the arena.
+    #[arg(long)]
+    pub spawns: Option<u8>,
+    /// File to write the map to, it is printed if absent.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+}
+
+impl Config {
+    /// Override the values loaded from the configuration file with the given arguments.
+    pub fn apply_args(&mut self, args: &Args) {
+        if let Some(bind) = args.bind {
+            self.bind_address = bind;
+        }
+        if let Some(port) = args.port {
+            self.port = port;
+        }
+        // map paths are relative to `map_dir`, make sure the extra ones stay relative to the
+        // working directory
+        self.map_paths.extend(
+            args.maps
+                .iter()
+                .map(|path| std::path::absolute(path).unwrap_or(path.clone()))
+                .map(|path| path.to_string_lossy().into_owned()),
+        );
+        if let Some(headless) = args.headless {
+            self.headless = headless;
+        }
+        if args.seed.is_some() {
+            self.seed = args.seed;
+        }
+        if args.record.is_some() {
+            self.record_path = args.record.clone();
+        }
+    }
+}
+
+impl Generator {
+    /// Override the configured generator settings with the given arguments.
+    pub fn apply_args(&mut self, args: &GeneratorArgs) {
+        if let Some([width, height]) = args.size.as_deref() {
+            self.size = [*width, *height].into();
+        }
+        if let Some(density) = args.density {
+            self.density = density;
+        }
+        if let Some(symmetry) = args.symmetry {
+            self.symmetry = symmetry;
+        }
+        if let Some(spawns) = args.spawns {
+            self.spawns = spawns;
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use bevy::{
+    ecs::entity::Entity,
+    math::{Dir2, Vec2},
+};
+
+use crate::entities::{Bullet, Wall};
+
+/// Contact found by a swept test.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    /// Fraction of the motion covered before the contact, from `0.0` to `1.0`.
+    pub time: f32,
+    /// Normal of the obstacle at the contact, pointing towards the moving circle.
+    pub normal: Dir2,
+}
+
+/// Point of the `wall` centered at `center` that is closest to `point`.
+pub fn closest_point(wall: &Wall, center: Vec2, point: Vec2) -> Vec2 {
+    let along = (point - center)
+        .dot(*wall.direction)
+        .clamp(-wall.half_length, wall.half_length);
+    center + wall.direction * along
+}
+
+/// Overlap of a circle of `radius` at `position` with the `wall` centered at `center`.
+///
+/// Returns the direction in which to push the circle out of the wall, and how far.
+/// Past the ends of the wall, the circle is pushed away from the endpoint.
+pub fn circle_wall_contact(
+    position: Vec2,
+    radius: f32,
+    wall: &Wall,
+    center: Vec2,
+) -> Option<(Dir2, f32)> {
+    let offset = position - closest_point(wall, center, position);
+    let distance = offset.length();
+    if distance >= radius {
+        return None;
+    }
+    // a circle centered on the wall is pushed out of its front
+    let normal = Dir2::new(offset).unwrap_or(wall.normal);
+    Some((normal, radius - distance))
+}
+
+/// Sweep a circle of `radius` from `start` by `motion` against a `wall` centered at `center`.
+/// The circle hits either side of the wall, or one of its endpoints, whose normal then
+/// points from the endpoint to the circle.
+/// A circle starting inside of the wall is not reported.
+pub fn sweep_circle_wall(
+    start: Vec2,
+    motion: Vec2,
+    radius: f32,
+    wall: &Wall,
+    center: Vec2,
+) -> Option<Hit> {
+    let sides = [wall.normal, -wall.normal]
+        .into_iter()
+        .filter_map(|normal| {
+            let distance = (start - center).dot(*normal) - radius;
+            let approach = -motion.dot(*normal);
+            // only the side the circle comes from can be hit
+            if distance < 0.0 || approach <= 0.0 || distance > approach {
+                return None;
+            }
+            let time = distance / approach;
+            let along = (start + motion * time - center).dot(*wall.direction);
+            (along.abs() <= wall.half_length).then_some(Hit { time, normal })
+        });
+    let ends = [1.0, -1.0].into_iter().filter_map(|end| {
+        let endpoint = center + wall.direction * wall.half_length * end;
+        sweep_point_circle(start, motion, radius, endpoint)
+    });
+    sides.chain(ends).min_by(|a, b| a.time.total_cmp(&b.time))
+}
+
+/// Sweep a point from `start` by `motion` against a circle of `radius` at `center`.
+/// To sweep a circle, `radius` is the sum of both radii.
+/// A point starting inside of the circle is not reported.
+pub fn sweep_point_circle(start: Vec2, motion: Vec2, radius: f32, center: Vec2) -> Option<Hit> {
+    let offset = start - center;
+    let a = motion.length_squared();
+    let b = 2.0 * motion.dot(offset);
+    let c = offset.length_squared() - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if a == 0.0 || c < 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    let time = (-b - discriminant.sqrt()) / (2.0 * a);
+    if !(0.0..=1.0).contains(&time) {
+        return None;
+    }
+    let normal = Dir2::new(offset + motion * time).ok()?;
+    Some(Hit { time, normal })
+}
+
+/// Move `bullet` from `position` by its velocity over `delta` seconds.
+/// The bullet bounces off the `walls`, given with their centers, as often as needed within
+/// the tick, and stops at the first of the `tanks` (entity, center and radius) it runs into,
+/// which is then stored in [`Bullet::hit`].
+///
+/// Returns the new position, or `None` if the bullet used up its bounces.
+pub fn trace_bullet(
+    bullet: &mut Bullet,
+    mut position: Vec2,
+    delta: f32,
+    walls: &[(&Wall, Vec2)],
+    tanks: &[(Entity, Vec2, f32)],
+) -> Option<Vec2> {
+    let mut motion = bullet.velocity * delta;
+    loop {
+        let wall_hit = walls
+            .iter()
+            .filter_map(|&(wall, center)| {
+                sweep_circle_wall(position, motion, bullet.radius, wall, center)
+            })
+            .min_by(|a, b| a.time.total_cmp(&b.time));
+        let tank_hit = tanks
+            .iter()
+            .filter_map(|&(tank, center, radius)| {
+                sweep_point_circle(position, motion, bullet.radius + radius, center)
+                    .map(|hit| (hit, tank))
+            })
+            .min_by(|(a, _), (b, _)| a.time.total_cmp(&b.time));
+
+        match (wall_hit, tank_hit) {
+            (_, Some((hit, tank))) if wall_hit.is_none_or(|wall| hit.time <= wall.time) => {
+                bullet.hit = Some(tank);
+                return Some(position + motion * hit.time);
+            }
+            (Some(hit), _) => {
+                if !bullet.add_bounce() {
+                    return None;
+                }
+                position += motion * hit.time;
+                motion = (motion * (1.0 - hit.time)).reflect(*hit.normal);
+                bullet.reflect(hit.normal);
+            }
+            _ => return Some(position + motion),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A wall of length `100` along the x axis, facing up.
+    fn thin_wall() -> Wall {
+        Wall {
+            normal: Dir2::Y,
+            direction: Dir2::X,
+            half_length: 50.0,
+        }
+    }
+
+    #[test]
+    fn test_closest_point() {
+        let wall = thin_wall();
+        let closest = |x, y| closest_point(&wall, Vec2::ZERO, Vec2::new(x, y));
+        assert_eq!(closest(20.0, 30.0), Vec2::new(20.0, 0.0));
+        assert_eq!(closest(80.0, -30.0), Vec2::new(50.0, 0.0));
+        assert_eq!(closest(-80.0, 5.0), Vec2::new(-50.0, 0.0));
+    }
+
+    #[test]
+    fn test_contact_sides() {
+        let wall = thin_wall();
+        let contact = |x, y| circle_wall_contact(Vec2::new(x, y), 10.0, &wall, Vec2::ZERO);
+        assert_eq!(contact(0.0, 6.0), Some((Dir2::Y, 4.0)));
+        // walls are solid from behind as well
+        assert_eq!(contact(0.0, -6.0), Some((Dir2::NEG_Y, 4.0)));
+        assert_eq!(contact(0.0, 0.0), Some((Dir2::Y, 10.0)));
+        assert_eq!(contact(0.0, 10.0), None);
+    }
+
+    #[test]
+    fn test_contact_endpoints() {
+        let wall = thin_wall();
+        let contact = |x, y| circle_wall_contact(Vec2::new(x, y), 10.0, &wall, Vec2::ZERO);
+        assert_eq!(contact(56.0, 0.0), Some((Dir2::X, 4.0)));
+        let (normal, depth) = contact(-53.0, -4.0).unwrap();
+        assert!((normal.as_vec2() - Vec2::new(-0.6, -0.8)).length() < 1e-6);
+        assert!((depth - 5.0).abs() < 1e-6);
+        // within the wall's length plus the radius, but beyond the rounded end
+        assert_eq!(contact(58.0, 8.0), None);
+    }
+
+    #[test]
+    fn test_convex_corner() {
+        // the top corner of a diamond, the tank sits right above it
+        let left = Wall {
+            normal: Dir2::new(Vec2::new(-1.0, 1.0)).unwrap(),
+            direction: Dir2::new(Vec2::new(1.0, 1.0)).unwrap(),
+            half_length: 50.0,
+        };
+        let right = Wall {
+            normal: Dir2::new(Vec2::new(1.0, 1.0)).unwrap(),
+            direction: Dir2::new(Vec2::new(1.0, -1.0)).unwrap(),
+            half_length: 50.0,
+        };
+        let corner = Vec2::ZERO;
+        let left_center = corner - left.direction * 50.0;
+        let right_center = corner + right.direction * 50.0;
+
+        let mut position = Vec2::new(0.0, 8.0);
+        for (wall, center) in [(&left, left_center), (&right, right_center)] {
+            if let Some((normal, depth)) = circle_wall_contact(position, 10.0, wall, center) {
+                position += normal * depth;
+            }
+        }
+        // pushed straight up by the corner, not sideways along either wall
+        assert!(position.x.abs() < 1e-4);
+        assert!((position.y - 10.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_sweep_wall() {
+        let wall = thin_wall();
+        let hit = sweep_circle_wall(
+            Vec2::new(0.0, -100.0),
+            Vec2::new(0.0, 1000.0),
+            5.0,
+            &wall,
+            Vec2::ZERO,
+        )
+        .unwrap();
+        assert!((hit.time - 0.095).abs() < 1e-6);
+        assert_eq!(hit.normal, Dir2::NEG_Y);
+
+        // passing beside the wall, or stopping short of it
+        let beside = Vec2::new(60.0, -100.0);
+        assert_eq!(
+            sweep_circle_wall(beside, Vec2::new(0.0, 1000.0), 5.0, &wall, Vec2::ZERO),
+            None
+        );
+        let short = Vec2::new(0.0, -100.0);
+        assert_eq!(
+            sweep_circle_wall(short, Vec2::new(0.0, 90.0), 5.0, &wall, Vec2::ZERO),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sweep_endpoint() {
+        let wall = thin_wall();
+        let hit = sweep_circle_wall(
+            Vec2::new(100.0, 3.0),
+            Vec2::new(-100.0, 0.0),
+            5.0,
+            &wall,
+            Vec2::ZERO,
+        )
+        .unwrap();
+        assert!((hit.time - 0.46).abs() < 1e-6);
+        assert!((hit.normal.as_vec2() - Vec2::new(0.8, 0.6)).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_corner_reflection() {
+        let wall = thin_wall();
+        let mut bullet = Bullet::new(Entity::PLACEHOLDER, Vec2::new(-6000.0, 0.0), 5.0, 2, 0.0);
+        let position = trace_bullet(
+            &mut bullet,
+            Vec2::new(100.0, 3.0),
+            1.0 / 60.0,
+            &[(&wall, Vec2::ZERO)],
+            &[],
+        )
+        .unwrap();
+        // reflected off the endpoint normal (0.8, 0.6)
+        assert!((bullet.velocity - Vec2::new(1680.0, 5760.0)).length() < 1e-2);
+        assert!(position.y > 3.0);
+    }
+
+    #[test]
+    fn test_sweep_circle() {
+        let hit = sweep_point_circle(
+            Vec2::new(-100.0, 0.0),
+            Vec2::new(1000.0, 0.0),
+            10.0,
+            Vec2::ZERO,
+        )
+        .unwrap();
+        assert!((hit.time - 0.09).abs() < 1e-6);
+        assert_eq!(hit.normal, Dir2::NEG_X);
+        assert_eq!(
+            sweep_point_circle(
+                Vec2::new(-100.0, 20.0),
+                Vec2::new(1000.0, 0.0),
+                10.0,
+                Vec2::ZERO
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_thin_wall_high_speed() {
+        // the bullet would end up far behind the wall without swept collision
+        let wall = thin_wall();
+        let mut bullet = Bullet::new(Entity::PLACEHOLDER, Vec2::new(0.0, 6000.0), 5.0, 2, 0.0);
+        let position = trace_bullet(
+            &mut bullet,
+            Vec2::new(0.0, -50.0),
+            1.0 / 60.0,
+            &[(&wall, Vec2::ZERO)],
+            &[],
+        )
+        .unwrap();
+        assert!(position.y < -5.0);
+        assert_eq!(bullet.velocity, Vec2::new(0.0, -6000.0));
+    }
+
+    #[test]
+    fn test_multiple_bounces() {
+        // a corridor of two thin walls, 20 apart
+        let wall = thin_wall();
+        let walls = [
+            (&wall, Vec2::new(0.0, 10.0)),
+            (&wall, Vec2::new(0.0, -10.0)),
+        ];
+        let mut bullet = Bullet::new(Entity::PLACEHOLDER, Vec2::new(0.0, 3000.0), 5.0, 5, 0.0);
+        let position = trace_bullet(&mut bullet, Vec2::ZERO, 1.0 / 60.0, &walls, &[]).unwrap();
+        assert!(position.y.abs() <= 5.0);
+
+        let mut bullet = Bullet::new(Entity::PLACEHOLDER, Vec2::new(0.0, 3000.0), 5.0, 2, 0.0);
+        assert_eq!(
+            trace_bullet(&mut bullet, Vec2::ZERO, 1.0 / 60.0, &walls, &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_tank_behind_wall() {
+        let wall = thin_wall();
+        let tank = Entity::from_raw(1);
+        let tanks = [(tank, Vec2::new(0.0, 50.0), 12.0)];
+
+        let mut bullet = Bullet::new(Entity::PLACEHOLDER, Vec2::new(0.0, 6000.0), 5.0, 0, 0.0);
+        let position = trace_bullet(
+            &mut bullet,
+            Vec2::new(0.0, -50.0),
+            1.0 / 60.0,
+            &[(&wall, Vec2::ZERO)],
+            &tanks,
+        );
+        assert_eq!(position, None);
+        assert_eq!(bullet.hit, None);
+
+        let mut bullet = Bullet::new(Entity::PLACEHOLDER, Vec2::new(0.0, 6000.0), 5.0, 0, 0.0);
+        let position =
+            trace_bullet(&mut bullet, Vec2::new(0.0, -50.0), 1.0 / 60.0, &[], &tanks).unwrap();
+        assert!((position.y - 33.0).abs() < 1e-3);
+        assert_eq!(bullet.hit, Some(tank));
+    }
+}
@@ -1,28 +1,66 @@
-use std::time::Duration;
+use std::{
+    fs::read_to_string,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use bevy::{
     ecs::{component::Component, resource::Resource},
     math::Vec2,
 };
+use messages::server::{MAX_TANKS, Parameters};
 use serde::Deserialize;
 
+use crate::{
+    generator::Generator, map::strip_comments, rounds::Rounds, scoring::Scoring,
+    systems::TankCollision, teams::Team,
+};
+
 /// Aggregate `struct` that holds the configuration for the server.
 /// This also includes the defaults for players.
-#[derive(Component, Resource, Deserialize)]
+#[derive(Component, Resource, Deserialize, Debug)]
+#[serde(default)]
 pub struct Config {
+    /// Address the server's UDP socket is bound to.
+    pub bind_address: IpAddr,
+    /// Port the server's UDP socket is bound to.
+    pub port: u16,
+    /// Run the simulation without opening a window.
+    pub headless: bool,
+    /// Seed for the random number generator, a random one is chosen if absent.
+    pub seed: Option<u64>,
+    /// File the match is recorded to, nothing is recorded if absent.
+    pub record_path: Option<PathBuf>,
+
     /// Timeout for player inactivity.
     /// If the player does not send a message within this delay, they will be despawned and
     /// penalised.
     pub inactivity_timeout: Duration,
+    /// Maximum number of players connected at the same time.
+    /// Further players are refused until someone leaves.
+    pub max_players: usize,
     /// Timeout after death.
     /// The player is respawned after this much time has passed.
     /// This is shared between players.
     pub respawn_delay: Duration,
 
+    /// Formula used to rank players on the leaderboard.
+    pub scoring: Scoring,
+    /// Length and number of the rounds, and the order of their maps.
+    pub rounds: Rounds,
+    /// Teams the players are divided into, free-for-all if empty.
+    pub teams: Vec<Team>,
+    /// Whether bullets destroy the tanks of the shooter's teammates.
+    pub friendly_fire: bool,
+
     /// Base directory for the map files.
     pub map_dir: String,
     /// Specific paths within `map_dir` that should be loaded.
     pub map_paths: Vec<String>,
+    /// Generate a fresh map for every round with these settings, instead of loading
+    /// `map_paths`.
+    pub map_generator: Option<Generator>,
 
     // Defaults for players
     /// Default radius of the tank.
@@ -45,17 +83,43 @@ pub struct Config {
     pub bullet_speed: f32,
     /// Default for the number of bounces that a bullet survives.
     pub bullet_max_bounces: i8,
+    /// Default hit points of a tank.
+    pub tank_health: f32,
+    /// Default damage dealt by a bullet.
+    pub bullet_damage: f32,
+
+    /// How tanks that run into each other are handled.
+    pub tank_collision: TankCollision,
+    /// Damage dealt to both tanks when they run into each other.
+    pub ram_damage: f32,
+    /// Relative speed at which a ram deals exactly `ram_damage`.
+    /// If set, the damage scales linearly with the relative speed of the tanks,
+    /// otherwise every ram deals `ram_damage`.
+    pub ram_reference_speed: Option<f32>,
 
     pub physics_steps: u8,
+    /// Number of simulation ticks per second.
+    pub tick_rate: f64,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 4000,
+            headless: false,
+            seed: None,
+            record_path: None,
             inactivity_timeout: Duration::new(5, 0),
+            max_players: 8,
             respawn_delay: Duration::new(5, 0),
+            scoring: Scoring::default(),
+            rounds: Rounds::default(),
+            teams: vec![],
+            friendly_fire: false,
             map_dir: "./assets/maps".into(),
             map_paths: vec!["layout-00.jsonc".to_owned()],
+            map_generator: None,
             tank_radius: 12.0,
             track_max_velocity: [500.0, 500.0].into(),
             track_max_acceleration: [100.0, 100.0].into(),
@@ -65,7 +129,62 @@ impl Default for Config {
             bullet_radius: 5.0,
             bullet_speed: 100.0,
             bullet_max_bounces: 2,
+            tank_health: 100.0,
+            bullet_damage: 35.0,
+            tank_collision: TankCollision::default(),
+            ram_damage: 20.0,
+            ram_reference_speed: None,
             physics_steps: 8,
+            tick_rate: 60.0,
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from a JSON file, allowing `//` comments.
+    /// Values missing from the file keep their [`Default`], and the whole default configuration
+    /// is used if the file is missing or incorrect.
+    /// `max_players` is clamped to the number of tanks a [`State`] message can hold.
+    ///
+    /// [`State`]: messages::server::ServerMessages::State
+    pub fn load(path: &Path) -> Self {
+        let mut config: Self = serde_json::from_str(&strip_comments(
+            &read_to_string(path)
+                .inspect_err(|err| eprintln!("Config {} not found:\n{err}", path.display()))
+                .unwrap_or_default(),
+        ))
+        .inspect(|_| eprintln!("correct format"))
+        .inspect_err(|err| {
+            eprintln!(
+                "Incorrect config, none of the values from {} are used:\n{err}",
+                path.display()
+            )
+        })
+        .unwrap_or_default();
+        if config.max_players > MAX_TANKS {
+            eprintln!(
+                "max_players is {}, but at most {MAX_TANKS} tanks fit in a state message, \
+                 using {MAX_TANKS}",
+                config.max_players
+            );
+            config.max_players = MAX_TANKS;
+        }
+        config
+    }
+
+    /// The default tank limits, as sent to the players.
+    pub fn parameters(&self) -> Parameters {
+        Parameters {
+            tank_radius: self.tank_radius,
+            track_max_velocity: self.track_max_velocity.to_array(),
+            track_max_acceleration: self.track_max_acceleration.to_array(),
+            turret_max_velocity: self.turret_max_velocity,
+            turret_max_acceleration: self.turret_max_acceleration,
+            shoot_delay: self.shoot_delay.as_secs_f32(),
+            bullet_radius: self.bullet_radius,
+            bullet_speed: self.bullet_speed,
+            bullet_max_bounces: self.bullet_max_bounces,
+            max_health: self.tank_health,
         }
     }
 }
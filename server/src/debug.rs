@@ -1,8 +1,11 @@
 use bevy::{
+    app::{App, Update},
     color::Color,
     ecs::{
         query::Without,
-        system::{Query, Res},
+        resource::Resource,
+        schedule::{Condition, IntoScheduleConfigs},
+        system::{Query, Res, ResMut},
     },
     gizmos::gizmos::Gizmos,
     input::{ButtonInput, keyboard::KeyCode},
@@ -15,6 +18,24 @@ use crate::{
     util::forget_z,
 };
 
+/// Whether the simulation is paused, toggled with the space bar.
+#[derive(Resource, Default, PartialEq, Eq)]
+pub struct Paused(pub bool);
+
+pub fn toggle_pause(keys: Res<ButtonInput<KeyCode>>, mut paused: ResMut<Paused>) {
+    if keys.just_pressed(KeyCode::Space) {
+        paused.0 = !paused.0;
+    }
+}
+
+/// Draw the debugging gizmos while their key is held:
+/// `D` for all of them, `N` for wall normals, `B` for collision bounds, `S` for spawns.
+pub fn debug_draw(app: &mut App) {
+    app.add_systems(Update, draw_normals.run_if(do_debug.or(do_normals)))
+        .add_systems(Update, draw_bounds.run_if(do_debug.or(do_bounds)))
+        .add_systems(Update, draw_spawns.run_if(do_debug.or(do_spawns)));
+}
+
 pub fn do_debug(keys: Res<ButtonInput<KeyCode>>) -> bool {
     keys.pressed(KeyCode::KeyD)
 }
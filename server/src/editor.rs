@@ -0,0 +1,612 @@
+use std::{
+    fs::{read_to_string, write},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    asset::AssetServer,
+    color::Color,
+    ecs::{
+        component::Component,
+        entity::Entity,
+        query::With,
+        resource::Resource,
+        system::{Commands, Query, Res, ResMut},
+    },
+    gizmos::gizmos::Gizmos,
+    input::{ButtonInput, keyboard::KeyCode, mouse::MouseButton},
+    log,
+    math::{Isometry2d, UVec2, Vec2},
+    render::camera::Camera,
+    transform::components::GlobalTransform,
+    ui::{BackgroundColor, Node, PositionType, UiRect, Val, widget::Text},
+    window::{PrimaryWindow, Window},
+};
+
+use crate::{
+    config::Config,
+    entities,
+    map::{MAP_VERSION, Map, Shape, Spawn, Wall},
+    validation::{distance, load_maps},
+};
+
+/// Spacing of the grid that points snap to.
+const SNAP: f32 = 10.0;
+/// Number of grid cells drawn in each direction.
+const GRID_CELLS: u32 = 200;
+/// Distance from the cursor within which a point can be dragged.
+const GRAB_DISTANCE: f32 = 8.0;
+/// Distance from the cursor within which a wall is picked.
+const PICK_DISTANCE: f32 = 6.0;
+/// Distance from the cursor within which a spawn is picked.
+const SPAWN_PICK_DISTANCE: f32 = 15.0;
+/// Number of edits that can be undone.
+const UNDO_LIMIT: usize = 100;
+
+/// A point of the map that can be dragged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Point {
+    /// The start (`0`) or end (`1`) of a wall.
+    Wall {
+        wall: usize,
+        end: usize,
+    },
+    /// A corner of a polygon or polyline, or the center of a circle.
+    Shape {
+        shape: usize,
+        point: usize,
+    },
+    Spawn(usize),
+}
+
+/// A part of the map that can be flipped or removed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Item {
+    Wall(usize),
+    Shape(usize),
+    Spawn(usize),
+}
+
+/// What the left mouse button is dragging.
+#[derive(Clone, Copy, Debug)]
+enum Drag {
+    /// An existing point, `moved` once the map was changed by the drag.
+    Point { point: Point, moved: bool },
+    /// A new wall starting at the given position.
+    Wall(Vec2),
+}
+
+fn shape_points(shape: &Shape) -> &[[f32; 2]] {
+    match shape {
+        Shape::Polygon { polygon, .. } => polygon,
+        Shape::Polyline { polyline } => polyline,
+        Shape::Circle { circle, .. } => std::slice::from_ref(circle),
+    }
+}
+
+fn shape_points_mut(shape: &mut Shape) -> &mut [[f32; 2]] {
+    match shape {
+        Shape::Polygon { polygon, .. } => polygon,
+        Shape::Polyline { polyline } => polyline,
+        Shape::Circle { circle, .. } => std::slice::from_mut(circle),
+    }
+}
+
+fn spawn_position_mut(spawn: &mut Spawn) -> &mut [f32; 2] {
+    match spawn {
+        Spawn::Position(position) | Spawn::Team { position, .. } => position,
+    }
+}
+
+/// All points of the map that can be dragged, with their positions.
+fn points(map: &Map) -> Vec<(Point, Vec2)> {
+    let walls = map.walls.iter().enumerate().flat_map(|(wall, data)| {
+        [
+            (Point::Wall { wall, end: 0 }, data.from.into()),
+            (Point::Wall { wall, end: 1 }, data.to.into()),
+        ]
+    });
+    let shapes = map.shapes.iter().enumerate().flat_map(|(shape, data)| {
+        shape_points(data)
+            .iter()
+            .enumerate()
+            .map(move |(point, &position)| (Point::Shape { shape, point }, position.into()))
+    });
+    let spawns = map
+        .spawns
+        .iter()
+        .enumerate()
+        .map(|(spawn, data)| (Point::Spawn(spawn), data.position()));
+    walls.chain(shapes).chain(spawns).collect()
+}
+
+/// The point closest to `at`, if it is close enough to be dragged.
+fn point_at(map: &Map, at: Vec2) -> Option<Point> {
+    points(map)
+        .into_iter()
+        .map(|(point, position)| (point, position.distance(at)))
+        .filter(|(_, distance)| *distance < GRAB_DISTANCE)
+        .min_by(|(_, first), (_, second)| first.total_cmp(second))
+        .map(|(point, _)| point)
+}
+
+/// The spawn, wall or shape under `at`, in that order.
+fn item_at(map: &Map, at: Vec2) -> Option<Item> {
+    if let Some(spawn) = map
+        .spawns
+        .iter()
+        .position(|spawn| spawn.position().distance(at) < SPAWN_PICK_DISTANCE)
+    {
+        return Some(Item::Spawn(spawn));
+    }
+    if let Some(wall) = map.walls.iter().position(|wall| {
+        wall.segment()
+            .is_some_and(|segment| distance(&segment, at) < PICK_DISTANCE)
+    }) {
+        return Some(Item::Wall(wall));
+    }
+    map.shapes
+        .iter()
+        .position(|shape| {
+            shape
+                .segments()
+                .iter()
+                .any(|segment| distance(segment, at) < PICK_DISTANCE)
+        })
+        .map(Item::Shape)
+}
+
+/// Normal on the left of the wall from `from` to `to`, or on its right.
+fn side_normal(from: Vec2, to: Vec2, right: bool) -> Option<[f32; 2]> {
+    let left = (to - from).perp().try_normalize()?;
+    // adding zero turns -0 into 0, which reads better in the file
+    Some((if right { -left } else { left } + Vec2::ZERO).to_array())
+}
+
+/// Whether the normal of `wall` is on the right of its direction.
+fn on_right(wall: &Wall) -> bool {
+    wall.segment()
+        .is_some_and(|segment| segment.normal.perp_dot(segment.to - segment.from) > 0.0)
+}
+
+/// Move a point of `map`, a wall keeps its normal on the same side.
+fn move_point(map: &mut Map, point: Point, position: Vec2) {
+    match point {
+        Point::Wall { wall, end } => {
+            let Some(wall) = map.walls.get_mut(wall) else {
+                return;
+            };
+            let right = on_right(wall);
+            let end = if end == 0 {
+                &mut wall.from
+            } else {
+                &mut wall.to
+            };
+            *end = position.to_array();
+            // without a normal it is on the left anyway
+            if wall.normal.is_some()
+                && let Some(normal) = side_normal(wall.from.into(), wall.to.into(), right)
+            {
+                wall.normal = Some(normal);
+            }
+        }
+        Point::Shape { shape, point } => {
+            if let Some(data) = map
+                .shapes
+                .get_mut(shape)
+                .and_then(|shape| shape_points_mut(shape).get_mut(point))
+            {
+                *data = position.to_array();
+            }
+        }
+        Point::Spawn(spawn) => {
+            if let Some(spawn) = map.spawns.get_mut(spawn) {
+                *spawn_position_mut(spawn) = position.to_array();
+            }
+        }
+    }
+}
+
+fn point_position(map: &Map, point: Point) -> Option<Vec2> {
+    points(map)
+        .into_iter()
+        .find(|(other, _)| *other == point)
+        .map(|(_, position)| position)
+}
+
+/// A map being edited, with the history of its changes.
+#[derive(Resource)]
+pub struct Editor {
+    path: PathBuf,
+    map: Map,
+    /// Versions of the map before each edit, the latest last.
+    undo: Vec<Map>,
+    /// Whether points snap to the grid.
+    snap: bool,
+    drag: Option<Drag>,
+    /// Position of the cursor in the world, if it is inside the window.
+    cursor: Option<Vec2>,
+    /// Whether the map changed since it was last spawned.
+    changed: bool,
+    /// Whether the map changed since it was last saved.
+    unsaved: bool,
+}
+
+impl Editor {
+    /// Open the map at `path`, or start an empty one if the file does not exist yet.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let map = match read_to_string(path) {
+            Ok(json) => Map::parse(&json)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Map {
+                version: MAP_VERSION,
+                name: path
+                    .file_stem()
+                    .map_or("New map".into(), |stem| stem.to_string_lossy().into_owned()),
+                background_path: None,
+                walls: vec![],
+                shapes: vec![],
+                spawns: vec![],
+            },
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            map,
+            undo: vec![],
+            snap: true,
+            drag: None,
+            cursor: None,
+            changed: true,
+            unsaved: false,
+        })
+    }
+
+    fn snapped(&self, at: Vec2) -> Vec2 {
+        if self.snap {
+            (at / SNAP).round() * SNAP
+        } else {
+            at
+        }
+    }
+
+    /// The map to change, remembering its current version for [`Editor::undo`].
+    fn edit(&mut self) -> &mut Map {
+        if self.undo.len() >= UNDO_LIMIT {
+            self.undo.remove(0);
+        }
+        self.undo.push(self.map.clone());
+        self.changed = true;
+        self.unsaved = true;
+        &mut self.map
+    }
+
+    /// Start dragging the point at `at`, or a new wall if there is none.
+    pub fn press(&mut self, at: Vec2) {
+        self.drag = Some(match point_at(&self.map, at) {
+            Some(point) => Drag::Point {
+                point,
+                moved: false,
+            },
+            None => Drag::Wall(self.snapped(at)),
+        });
+    }
+
+    /// Move the dragged point to `at`.
+    pub fn drag(&mut self, at: Vec2) {
+        let at = self.snapped(at);
+        let Some(Drag::Point { point, moved }) = self.drag else {
+            return;
+        };
+        if point_position(&self.map, point) == Some(at) {
+            return;
+        }
+        // the whole drag is undone at once
+        let map = if moved {
+            self.changed = true;
+            &mut self.map
+        } else {
+            self.drag = Some(Drag::Point { point, moved: true });
+            self.edit()
+        };
+        move_point(map, point, at);
+    }
+
+    /// Finish the drag, a new wall ends at `at`.
+    pub fn release(&mut self, at: Vec2) {
+        let at = self.snapped(at);
+        if let Some(Drag::Wall(start)) = self.drag.take()
+            && let Some(normal) = side_normal(start, at, false)
+        {
+            self.edit().walls.push(Wall {
+                from: start.to_array(),
+                to: at.to_array(),
+                normal: Some(normal),
+            });
+        }
+    }
+
+    /// Remove the spawn at `at`, or add one there if there is none.
+    pub fn toggle_spawn(&mut self, at: Vec2) {
+        match item_at(&self.map, at) {
+            Some(Item::Spawn(spawn)) => {
+                self.edit().spawns.remove(spawn);
+            }
+            _ => {
+                let at = self.snapped(at);
+                self.edit().spawns.push(Spawn::Position(at.to_array()));
+            }
+        }
+    }
+
+    /// Turn the wall at `at` around, or a polygon or circle inside out.
+    pub fn flip(&mut self, at: Vec2) {
+        match item_at(&self.map, at) {
+            Some(Item::Wall(wall)) => {
+                let Wall { from, to, .. } = self.map.walls[wall];
+                let right = on_right(&self.map.walls[wall]);
+                if let Some(normal) = side_normal(from.into(), to.into(), !right) {
+                    self.edit().walls[wall].normal = Some(normal);
+                }
+            }
+            // polylines face both ways
+            Some(Item::Shape(shape))
+                if !matches!(self.map.shapes[shape], Shape::Polyline { .. }) =>
+            {
+                if let Shape::Polygon { inward, .. } | Shape::Circle { inward, .. } =
+                    &mut self.edit().shapes[shape]
+                {
+                    *inward = !*inward;
+                }
+            }
+            Some(Item::Shape(_) | Item::Spawn(_)) | None => {}
+        }
+    }
+
+    /// Remove the spawn, wall or shape at `at`.
+    pub fn remove(&mut self, at: Vec2) {
+        match item_at(&self.map, at) {
+            Some(Item::Spawn(spawn)) => {
+                self.edit().spawns.remove(spawn);
+            }
+            Some(Item::Wall(wall)) => {
+                self.edit().walls.remove(wall);
+            }
+            Some(Item::Shape(shape)) => {
+                self.edit().shapes.remove(shape);
+            }
+            None => {}
+        }
+    }
+
+    /// Go back to the map before the last edit.
+    pub fn undo(&mut self) {
+        if let Some(map) = self.undo.pop() {
+            self.map = map;
+            self.drag = None;
+            self.changed = true;
+            self.unsaved = true;
+        }
+    }
+
+    /// Write the map back to its file.
+    /// The file is rewritten from the parsed map, so any `//` comments in it are lost.
+    pub fn save(&mut self) -> io::Result<()> {
+        write(&self.path, self.map.to_json())?;
+        self.unsaved = false;
+        Ok(())
+    }
+}
+
+/// Mouse and keyboard controls of the editor:
+/// - left drag: move a wall end, shape corner or spawn, or draw a new wall from empty space
+/// - right click: add a spawn, or remove the one under the cursor
+/// - `F`: flip the normal of the wall under the cursor, or turn a polygon or circle inside out
+/// - delete / backspace: remove the spawn, wall or shape under the cursor
+/// - `G`: toggle snapping to the grid
+/// - ctrl + `Z`: undo
+/// - ctrl + `S`: save the map, checking it like the maps loaded by the server
+pub fn edit_map(
+    keys: Res<ButtonInput<KeyCode>>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    config: Res<Config>,
+    mut editor: ResMut<Editor>,
+) {
+    let control = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if control && keys.just_pressed(KeyCode::KeyZ) {
+        editor.undo();
+    }
+    if control && keys.just_pressed(KeyCode::KeyS) {
+        match editor.save() {
+            Ok(()) => {
+                println!("Saved map {}", editor.path.display());
+                load_maps(std::slice::from_ref(&editor.path), &config);
+            }
+            Err(err) => log::error!("Could not save map {}:\n{err}", editor.path.display()),
+        }
+    }
+    if keys.just_pressed(KeyCode::KeyG) {
+        editor.snap = !editor.snap;
+    }
+
+    editor.cursor = windows
+        .single()
+        .ok()
+        .and_then(Window::cursor_position)
+        .zip(cameras.single().ok())
+        .and_then(|(cursor, (camera, transform))| {
+            camera.viewport_to_world_2d(transform, cursor).ok()
+        });
+    let Some(cursor) = editor.cursor else {
+        return;
+    };
+    if buttons.just_pressed(MouseButton::Left) {
+        editor.press(cursor);
+    }
+    if buttons.pressed(MouseButton::Left) {
+        editor.drag(cursor);
+    }
+    if buttons.just_released(MouseButton::Left) {
+        editor.release(cursor);
+    }
+    if buttons.just_pressed(MouseButton::Right) {
+        editor.toggle_spawn(cursor);
+    }
+    if keys.just_pressed(KeyCode::KeyF) {
+        editor.flip(cursor);
+    }
+    if keys.any_just_pressed([KeyCode::Delete, KeyCode::Backspace]) {
+        editor.remove(cursor);
+    }
+}
+
+/// Spawn the edited map again after every change, so that it is shown like a loaded map.
+pub fn show_edited_map(
+    mut commands: Commands,
+    asset_server: Option<Res<AssetServer>>,
+    mut editor: ResMut<Editor>,
+    shown: Query<Entity, With<entities::Map>>,
+) {
+    if !editor.changed {
+        return;
+    }
+    editor.changed = false;
+    shown
+        .iter()
+        .for_each(|entity| commands.entity(entity).despawn());
+    editor.map.spawn(commands, asset_server);
+}
+
+/// Draw the grid, the points that can be dragged and the wall being drawn.
+/// The walls, normals and spawns are drawn by the gizmos of [`crate::debug`].
+pub fn draw_editor(mut gizmos: Gizmos, editor: Res<Editor>) {
+    if editor.snap {
+        gizmos.grid_2d(
+            Isometry2d::IDENTITY,
+            UVec2::splat(GRID_CELLS),
+            Vec2::splat(SNAP),
+            Color::srgba(1.0, 1.0, 1.0, 0.05),
+        );
+    }
+    let hovered = editor
+        .cursor
+        .and_then(|cursor| point_at(&editor.map, cursor));
+    for (point, position) in points(&editor.map) {
+        let color = if Some(point) == hovered {
+            Color::srgb(1.0, 1.0, 0.3)
+        } else {
+            Color::WHITE
+        };
+        gizmos.circle_2d(Isometry2d::from_translation(position), 4.0, color);
+    }
+    if let (Some(Drag::Wall(start)), Some(cursor)) = (editor.drag, editor.cursor) {
+        gizmos.line_2d(start, editor.snapped(cursor), Color::srgb(1.0, 1.0, 0.3));
+    }
+}
+
+/// Marker for the text showing the edited file.
+#[derive(Component)]
+#[require(Text)]
+pub struct EditorStatus;
+
+pub fn setup_editor_status(mut commands: Commands) {
+    commands.spawn((
+        EditorStatus,
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(4.0),
+            bottom: Val::Px(4.0),
+            padding: UiRect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
+    ));
+}
+
+pub fn show_editor_status(editor: Res<Editor>, mut status: Query<&mut Text, With<EditorStatus>>) {
+    for mut text in &mut status {
+        text.0 = format!(
+            "{}{}{}",
+            editor.path.display(),
+            if editor.unsaved { "  unsaved" } else { "" },
+            if editor.snap { "  snap" } else { "" }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor() -> Editor {
+        Editor::open(Path::new("/nonexistent/new.jsonc")).unwrap()
+    }
+
+    #[test]
+    fn test_walls() {
+        let mut editor = editor();
+        assert_eq!(editor.map.name, "new");
+        editor.press(Vec2::new(1.0, -2.0));
+        editor.release(Vec2::new(99.0, 3.0));
+        let wall = &editor.map.walls[0];
+        assert_eq!((wall.from, wall.to), ([0.0, 0.0], [100.0, 0.0]));
+        assert_eq!(wall.normal, Some([0.0, 1.0]));
+
+        // dragging an end keeps the normal perpendicular, on the same side
+        editor.flip(Vec2::new(50.0, 2.0));
+        editor.press(Vec2::new(97.0, 1.0));
+        editor.drag(Vec2::new(100.0, 30.0));
+        editor.drag(Vec2::new(100.0, 50.0));
+        editor.release(Vec2::new(100.0, 50.0));
+        let segment = editor.map.walls[0].segment().unwrap();
+        assert_eq!(segment.to, Vec2::new(100.0, 50.0));
+        assert!(segment.normal.dot(segment.to - segment.from).abs() < 1e-4);
+        assert!(segment.normal.y < 0.0);
+
+        editor.undo();
+        assert_eq!(editor.map.walls[0].to, [100.0, 0.0]);
+        assert_eq!(editor.map.walls[0].normal, Some([0.0, -1.0]));
+        editor.undo();
+        assert_eq!(editor.map.walls[0].normal, Some([0.0, 1.0]));
+        editor.remove(Vec2::new(50.0, 0.0));
+        assert!(editor.map.walls.is_empty());
+        editor.undo();
+        editor.undo();
+        assert!(editor.map.walls.is_empty());
+        assert!(editor.undo.is_empty());
+    }
+
+    #[test]
+    fn test_spawns() {
+        let mut editor = editor();
+        editor.toggle_spawn(Vec2::new(23.0, 18.0));
+        assert_eq!(editor.map.spawns[0].position(), Vec2::new(20.0, 20.0));
+        editor.snap = false;
+        editor.press(Vec2::new(25.0, 15.0));
+        editor.drag(Vec2::new(33.0, 44.5));
+        editor.release(Vec2::new(33.0, 44.5));
+        assert_eq!(editor.map.spawns[0].position(), Vec2::new(33.0, 44.5));
+        assert!(editor.map.walls.is_empty());
+        editor.toggle_spawn(Vec2::new(30.0, 40.0));
+        assert!(editor.map.spawns.is_empty());
+    }
+
+    #[test]
+    fn test_save() {
+        let path =
+            std::env::temp_dir().join(format!("editor-test-map-{}.jsonc", std::process::id()));
+        let mut editor = Editor::open(&path).unwrap();
+        editor.press(Vec2::ZERO);
+        editor.release(Vec2::new(0.0, 100.0));
+        editor.toggle_spawn(Vec2::new(50.0, 50.0));
+        editor.save().unwrap();
+        assert!(!editor.unsaved);
+
+        let saved = Editor::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved.map.walls.len(), 1);
+        assert_eq!(saved.map.spawns.len(), 1);
+    }
+}
@@ -1,30 +1,66 @@
-use std::{net::UdpSocket, time::Duration};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
 
 use bevy::{
     asset::AssetServer,
+    color::Color,
     ecs::{
         component::Component,
+        entity::Entity,
         resource::Resource,
         system::{Commands, Res},
     },
-    math::{Dir2, Vec2, Vec3},
+    log,
+    math::{Dir2, Quat, Vec2, Vec3},
     render::view::Visibility,
-    sprite::Sprite,
+    sprite::{Anchor, Sprite, SpriteImageMode},
     transform::components::Transform,
 };
+use messages::{
+    PROTOCOL_VERSION,
+    server::{Parameters, ServerMessages},
+};
+use rand::rngs::StdRng;
 
-use crate::config::Config;
+use crate::{
+    config::Config,
+    teams::Team,
+    util::{load_sprite, with_z},
+};
 
 /// For easy access in the systems, we bundle the [`UdpSocket`] as a [`Resource`].
 #[derive(Component, Resource)]
 pub struct Socket(pub UdpSocket);
 
+impl Socket {
+    /// Send a message to the player listening at `address`, logging any failure.
+    pub fn send(&self, msg: &ServerMessages, address: SocketAddr) {
+        if let Err(err) = self.0.send_to(&msg.to_vec(), address) {
+            log::warn!("Sending to player @ {address} failed with {err}");
+        }
+    }
+}
+
+/// Random number generator driving all game randomness (spawn points, map choice, ...).
+/// Seeded from the [`Config`], so that matches can be reproduced.
+#[derive(Resource)]
+pub struct GameRng(pub StdRng);
+
 /// Struct corresponding to a connected player.
 /// It shall contain any stats of the player, to allow for upgrades.
 #[derive(Component, Default)]
 pub struct Player {
-    /// The port where we can send the messages to the player.
-    pub port: u16,
+    /// Identifier of the player, unique for the lifetime of the server.
+    pub id: u32,
+    /// Name shown on the leaderboard.
+    pub name: String,
+    /// Team of the player, `None` when playing free-for-all.
+    pub team: Option<u8>,
+    /// The address the player's messages come from, where we send the messages to the player.
+    /// `None` for players rebuilt from a replay.
+    pub address: Option<SocketAddr>,
     /// Some rudamentary form of security, as random generated secret upon connection.
     pub secret: u128,
     /// Timer for inactivity.
@@ -32,8 +68,14 @@ pub struct Player {
 
     /// Counter for the number of deaths.
     deaths: u32,
+    /// Counter for the number of enemy tanks destroyed by the player's bullets.
+    kills: u32,
+    /// Counter for the number of times the player was hit by their own bullet.
+    self_kills: u32,
     /// Respawn delay
     pub respawn_timer: Option<Duration>,
+    /// Total time the player's tank has been alive.
+    pub survival_time: Duration,
 
     // sprite information
     /// Path to the tank sprite.
@@ -42,10 +84,14 @@ pub struct Player {
     pub turret_sprite_path: String,
     /// Path to the bullet sprite.
     pub bullet_sprite_path: String,
+    /// Colour the tank and turret sprites are tinted with.
+    pub color: Color,
 
     // tank properties
     /// Radius of the tank's collision circle.
     pub tank_radius: f32,
+    /// Hit points of a freshly spawned tank.
+    pub max_health: f32,
     /// Maximum allowed track speed.
     pub track_max_velocity: Vec2,
     /// Maximum allowed track acceleration.
@@ -74,65 +120,140 @@ pub struct Player {
     pub bullet_speed: f32,
     /// Number of times the player's bullets can bounce.
     pub bullet_max_bounces: i8,
+    /// Damage dealt by the player's bullets.
+    pub bullet_damage: f32,
 }
 
 impl Player {
     /// Spawn a new player in.
     /// This will immediately spawn them a tank.
+    ///
+    /// Returns the [`ServerMessages::Welcome`] that should be sent to the player.
     #[allow(clippy::too_many_arguments)]
     pub fn spawn(
-        port: u16,
+        id: u32,
+        address: SocketAddr,
         secret: u128,
+        team: Option<u8>,
         position: Vec3,
         tank_sprite_path: String,
         turret_sprite_path: String,
         bullet_sprite_path: String,
         commands: &mut Commands,
         config: &Res<Config>,
-        asset_server: &Res<AssetServer>,
+        asset_server: &Option<Res<AssetServer>>,
+    ) -> ServerMessages {
+        let player = Player {
+            id,
+            name: format!("Player {id}"),
+            team,
+            address: Some(address),
+            secret,
+            tank_sprite_path,
+            turret_sprite_path,
+            bullet_sprite_path,
+            color: team
+                .and_then(|team| config.teams.get(usize::from(team)))
+                .map_or(Color::WHITE, Team::color),
+            tank_radius: config.tank_radius,
+            max_health: config.tank_health,
+            track_max_velocity: config.track_max_velocity,
+            track_max_acceleration: config.track_max_acceleration,
+            turret_max_velocity: config.turret_max_velocity,
+            turret_max_acceleration: config.turret_max_acceleration,
+            shoot_delay: config.shoot_delay,
+            bullet_radius: config.bullet_radius,
+            bullet_speed: config.bullet_speed,
+            bullet_max_bounces: config.bullet_max_bounces,
+            bullet_damage: config.bullet_damage,
+            ..Default::default()
+        };
+        let welcome = player.welcome();
+        let entity = commands
+            .spawn((Transform::default(), Visibility::Visible))
+            .id();
+        player.spawn_tank(entity, position, commands, asset_server);
+        commands.entity(entity).insert(player);
+        welcome
+    }
+
+    /// Rebuild a player shown in a replay.
+    /// Only the values displayed on the leaderboard are restored.
+    pub fn replayed(
+        id: u32,
+        name: String,
+        team: Option<u8>,
+        kills: u32,
+        deaths: u32,
+        survival_time: Duration,
+    ) -> Self {
+        Player {
+            id,
+            name,
+            team,
+            kills,
+            deaths,
+            survival_time,
+            ..Default::default()
+        }
+    }
+
+    /// Give the player, spawned as `entity`, a new tank at `position`.
+    pub fn spawn_tank(
+        &self,
+        entity: Entity,
+        position: Vec3,
+        commands: &mut Commands,
+        asset_server: &Option<Res<AssetServer>>,
     ) {
-        commands
-            .spawn((
-                Player {
-                    port,
-                    secret,
-                    tank_sprite_path: tank_sprite_path.clone(),
-                    turret_sprite_path: turret_sprite_path.clone(),
-                    bullet_sprite_path,
-                    tank_radius: config.tank_radius,
-                    track_max_velocity: config.track_max_velocity,
-                    track_max_acceleration: config.track_max_acceleration,
-                    turret_max_velocity: config.turret_max_velocity,
-                    turret_max_acceleration: config.turret_max_acceleration,
-                    shoot_delay: config.shoot_delay,
-                    bullet_radius: config.bullet_radius,
-                    bullet_speed: config.bullet_speed,
-                    bullet_max_bounces: config.bullet_max_bounces,
-                    ..Default::default()
-                },
-                Transform::default(),
-                Visibility::Visible,
-            ))
-            .with_children(|parent| {
-                parent
-                    .spawn((
-                        Tank {
-                            radius: config.tank_radius,
-                            track_max_velocity: config.track_max_velocity,
-                            ..Default::default()
-                        },
-                        Transform::from_translation(position),
-                        Sprite::from_image(asset_server.load(tank_sprite_path)),
-                    ))
-                    .with_child((
-                        Turret {
-                            max_velocity: config.turret_max_velocity,
-                            ..Default::default()
-                        },
-                        Transform::from_xyz(0.0, 3.0, 0.0),
-                        Sprite::from_image(asset_server.load(turret_sprite_path)),
-                    ));
-            });
+        commands.entity(entity).with_children(|parent| {
+            parent
+                .spawn((
+                    Tank {
+                        radius: self.tank_radius,
+                        track_max_velocity: self.track_max_velocity,
+                        health: self.max_health,
+                        ..Default::default()
+                    },
+                    Transform::from_translation(position),
+                    Sprite {
+                        color: self.color,
+                        ..load_sprite(asset_server, &self.tank_sprite_path)
+                    },
+                ))
+                .with_child((
+                    Turret {
+                        max_velocity: self.turret_max_velocity,
+                        ..Default::default()
+                    },
+                    Transform::from_xyz(0.0, 3.0, 0.0),
+                    Sprite {
+                        color: self.color,
+                        ..load_sprite(asset_server, &self.turret_sprite_path)
+                    },
+                ));
+        });
+    }
+
+    /// Build the [`ServerMessages::Welcome`] holding the player's id, secret and tank limits.
+    pub fn welcome(&self) -> ServerMessages {
+        ServerMessages::Welcome {
+            version: PROTOCOL_VERSION,
+            player_id: self.id,
+            secret: self.secret,
+            parameters: Parameters {
+                tank_radius: self.tank_radius,
+                track_max_velocity: self.track_max_velocity.to_array(),
+                track_max_acceleration: self.track_max_acceleration.to_array(),
+                turret_max_velocity: self.turret_max_velocity,
+                turret_max_acceleration: self.turret_max_acceleration,
+                shoot_delay: self.shoot_delay.as_secs_f32(),
+                bullet_radius: self.bullet_radius,
+                bullet_speed: self.bullet_speed,
+                bullet_max_bounces: self.bullet_max_bounces,
+                max_health: self.max_health,
+            },
+        }
     }
 
     /// Clear last input given by the player.
@@ -152,9 +273,37 @@ impl Player {
         self.respawn_timer = Some(Duration::new(0, 0));
     }
 
+    /// The player's bullet destroyed an enemy tank.
+    pub fn kill(&mut self) {
+        self.kills += 1;
+    }
+
+    /// The player's tank was destroyed by its own bullet.
+    /// This counts as a death as well.
+    pub fn self_kill(&mut self) {
+        self.self_kills += 1;
+        self.death();
+    }
+
+    /// Forget the kills, deaths and survival time of the player.
+    pub fn reset_stats(&mut self) {
+        self.kills = 0;
+        self.deaths = 0;
+        self.self_kills = 0;
+        self.survival_time = Duration::default();
+    }
+
     pub fn get_deaths(&self) -> u32 {
         self.deaths
     }
+
+    pub fn get_kills(&self) -> u32 {
+        self.kills
+    }
+
+    pub fn get_self_kills(&self) -> u32 {
+        self.self_kills
+    }
 }
 
 /// Holds physics data for a tank.
@@ -170,6 +319,33 @@ pub struct Tank {
     pub radius: f32,
     /// Maximum velocity allowed for the tracks.
     pub track_max_velocity: Vec2,
+    /// Hit points left, the tank is destroyed when they reach `0`.
+    pub health: f32,
+    /// Other tanks currently touching this one, a ram only deals damage when the contact begins.
+    pub contacts: Vec<Entity>,
+}
+
+impl Tank {
+    /// Velocity of the tank's center, `transform` being the tank's [`Transform`].
+    pub fn velocity(&self, transform: &Transform) -> Vec2 {
+        transform.up().truncate() * (self.track_velocities.x + self.track_velocities.y) * 0.5
+    }
+
+    /// Change the velocity of the tank by `impulse`, `transform` being the tank's [`Transform`].
+    /// The tracks only roll forwards and backwards, so the sideways part of the impulse is lost.
+    pub fn push(&mut self, transform: &Transform, impulse: Vec2) {
+        let forward = impulse.dot(transform.up().truncate());
+        self.track_velocities = (self.track_velocities + Vec2::splat(forward))
+            .clamp(-self.track_max_velocity, self.track_max_velocity);
+    }
+
+    /// Take `amount` of damage.
+    ///
+    /// Returns `true` if the tank is destroyed.
+    pub fn damage(&mut self, amount: f32) -> bool {
+        self.health -= amount;
+        self.health <= 0.0
+    }
 }
 
 #[derive(Component, Default)]
@@ -188,6 +364,8 @@ pub struct Turret {
 #[derive(Component)]
 #[require(Transform, Sprite)]
 pub struct Bullet {
+    /// The [`Player`] that fired the bullet.
+    owner: Entity,
     /// Current velocity of the bullet (direction and speed).
     pub velocity: Vec2,
     /// Radius of the bullets collision circle.
@@ -196,19 +374,28 @@ pub struct Bullet {
     max_bounces: i8,
     /// Number of times the bullet has already bounced.
     bounces: i8,
+    /// Damage dealt to the tank that is hit.
+    pub damage: f32,
+    /// Tank the bullet ran into during its last move.
+    pub hit: Option<Entity>,
 }
 
 impl Bullet {
     /// Create a new bullet.
+    /// - `owner`: the [`Player`] entity that fired the bullet
     /// - `velocity`: speed and direction of the bullet
     /// - `radius`: size of the collision circle
     /// - `max_bounces`: number of bounces allowed
-    pub fn new(velocity: Vec2, radius: f32, max_bounces: i8) -> Self {
+    /// - `damage`: damage dealt to the tank that is hit
+    pub fn new(owner: Entity, velocity: Vec2, radius: f32, max_bounces: i8, damage: f32) -> Self {
         Self {
+            owner,
             velocity,
             radius,
             max_bounces,
             bounces: 0,
+            damage,
+            hit: None,
         }
     }
 
@@ -220,6 +407,11 @@ impl Bullet {
         self.bounces <= self.max_bounces
     }
 
+    /// The [`Player`] entity that fired the bullet.
+    pub fn owner(&self) -> Entity {
+        self.owner
+    }
+
     pub fn reflect(&mut self, normal: Dir2) {
         self.velocity = self.velocity.reflect(normal.as_vec2());
     }
@@ -230,7 +422,10 @@ impl Bullet {
 /// The [`Transform`] is the origin of the map.
 #[derive(Component, Resource)]
 #[require(Transform)]
-pub struct Map;
+pub struct Map {
+    /// Name of the map, as given in its JSON file.
+    pub name: String,
+}
 
 /// Data for a wall, should be bundled with a [`Transform`] and a [`Sprite`].
 #[derive(Component)]
@@ -248,8 +443,57 @@ pub struct Wall {
     pub half_length: f32,
 }
 
-/// Marker for the spawn points in a [`Map`].
+/// Thickness of the drawn walls, the height of `wall.png`.
+pub const WALL_THICKNESS: f32 = 5.0;
+
+impl Wall {
+    /// Transform and sprite of the wall centered at `center`, `wall.png` tiled along its length.
+    /// The sprite lies behind the face of the wall, so that tanks stop right at the drawn wall.
+    pub fn display(
+        &self,
+        center: Vec2,
+        asset_server: &Option<Res<AssetServer>>,
+    ) -> (Transform, Sprite) {
+        // the sprite runs along the direction, its top is on the left of it
+        let transform = Transform::from_translation(with_z(center, 0.0))
+            .with_rotation(Quat::from_rotation_z(self.direction.to_angle()));
+        let facing_left = self.normal.dot(self.direction.perp()) > 0.0;
+        let sprite = Sprite {
+            custom_size: Some(Vec2::new(2.0 * self.half_length, WALL_THICKNESS)),
+            image_mode: SpriteImageMode::Tiled {
+                tile_x: true,
+                tile_y: false,
+                stretch_value: 1.0,
+            },
+            anchor: if facing_left {
+                Anchor::TopCenter
+            } else {
+                Anchor::BottomCenter
+            },
+            ..load_sprite(asset_server, "wall.png")
+        };
+        (transform, sprite)
+    }
+
+    /// Convert the wall, placed at `transform` within `map`, to its network representation.
+    pub fn to_message(&self, transform: &Transform, map: &Transform) -> messages::server::Wall {
+        let center = map.transform_point(transform.translation);
+        let direction = map.rotation * self.direction.extend(0.0);
+        let normal = map.rotation * self.normal.extend(0.0);
+        let origin = center - direction * self.half_length;
+        messages::server::Wall {
+            origin: [origin.x, origin.y],
+            direction_length: [
+                direction.x * 2.0 * self.half_length,
+                direction.y * 2.0 * self.half_length,
+            ],
+            normal: [normal.x, normal.y],
+        }
+    }
+}
+
+/// Marker for the spawn points in a [`Map`], holding the team it is reserved for.
 /// Should be bundled together with a [`Transform`].
 #[derive(Component)]
 #[require(Transform)]
-pub struct Spawn();
+pub struct Spawn(pub Option<u8>);
@@ -0,0 +1,26 @@
+use bevy::ecs::event::Event;
+
+/// Something noteworthy that happened during a simulation tick.
+#[derive(Event, Clone, Debug, PartialEq)]
+pub enum GameEvent {
+    /// A new player connected.
+    Joined { player: u32, name: String },
+    /// A player was disconnected.
+    Left { player: u32 },
+    /// A player's tank fired a bullet.
+    Shot { player: u32 },
+    /// A player's tank was destroyed.
+    /// `killer` is the player whose bullet hit the tank, the victim itself for a self-kill,
+    /// and `None` if the tank was destroyed in a collision.
+    Died { player: u32, killer: Option<u32> },
+}
+
+/// A [`ClientMessages::Control`](messages::client::ClientMessages::Control) accepted from a
+/// player.
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub struct ControlReceived {
+    pub player: u32,
+    pub tracks_acceleration_target: [f32; 2],
+    pub turret_acceleration_target: f32,
+    pub shoot: bool,
+}
@@ -0,0 +1,389 @@
+use std::collections::VecDeque;
+
+use bevy::math::{IVec2, Vec2};
+use clap::ValueEnum;
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use serde::Deserialize;
+
+use messages::server::{MAX_SPAWNS, MAX_WALLS};
+
+use crate::map::{MAP_VERSION, Map, Shape, Spawn, Wall};
+
+/// Side length of the cells the arena is divided into.
+/// Obstacles are made of whole cells, and a free cell leaves room for a tank to pass.
+pub const CELL_SIZE: f32 = 50.0;
+/// Minimum number of cells between two spawns, in both directions.
+const SPAWN_SPACING: i32 = 3;
+/// Walls the obstacles can use, the outer walls take the other 4 that fit in a map change.
+const MAX_OBSTACLE_WALLS: usize = MAX_WALLS - 4;
+
+/// How the parts of a generated arena mirror each other, each player's surroundings look the
+/// same from every spawn.
+#[derive(Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Symmetry {
+    /// The left and right half mirror each other.
+    #[default]
+    Mirror,
+    /// The arena looks the same after half a turn.
+    Rotational2,
+    /// The arena looks the same after a quarter turn, which makes it square.
+    Rotational4,
+}
+
+/// Settings of the procedural map generator.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Generator {
+    /// Width and height of the arena, rounded to whole cells.
+    pub size: Vec2,
+    /// Fraction of the arena covered by obstacles, fewer are placed if they would cut off
+    /// part of the arena.
+    pub density: f32,
+    pub symmetry: Symmetry,
+    /// Number of spawns in each symmetric part of the arena.
+    pub spawns: u8,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self {
+            size: Vec2::new(800.0, 600.0),
+            density: 0.25,
+            symmetry: Symmetry::default(),
+            spawns: 2,
+        }
+    }
+}
+
+/// The cells of an arena, each either free or blocked by an obstacle.
+struct Grid {
+    size: IVec2,
+    symmetry: Symmetry,
+    blocked: Vec<bool>,
+}
+
+impl Grid {
+    fn index(&self, cell: IVec2) -> usize {
+        (cell.y * self.size.x + cell.x) as usize
+    }
+
+    fn contains(&self, cell: IVec2) -> bool {
+        cell.cmpge(IVec2::ZERO).all() && cell.cmplt(self.size).all()
+    }
+
+    /// Whether `cell` is blocked, outside of the arena counts as blocked.
+    fn blocked(&self, cell: IVec2) -> bool {
+        !self.contains(cell) || self.blocked[self.index(cell)]
+    }
+
+    fn set(&mut self, cell: IVec2, blocked: bool) {
+        let index = self.index(cell);
+        self.blocked[index] = blocked;
+    }
+
+    fn cells(&self) -> impl Iterator<Item = IVec2> + use<> {
+        let size = self.size;
+        (0..size.y).flat_map(move |y| (0..size.x).map(move |x| IVec2::new(x, y)))
+    }
+
+    /// `cell` and the cells it corresponds to in the other parts of the arena.
+    fn images(&self, cell: IVec2) -> Vec<IVec2> {
+        let last = self.size - 1;
+        let mut images = match self.symmetry {
+            Symmetry::Mirror => vec![cell, IVec2::new(last.x - cell.x, cell.y)],
+            Symmetry::Rotational2 => vec![cell, last - cell],
+            Symmetry::Rotational4 => {
+                let turn = |cell: IVec2| IVec2::new(last.y - cell.y, cell.x);
+                vec![cell, turn(cell), turn(turn(cell)), turn(turn(turn(cell)))]
+            }
+        };
+        images.sort_by_key(|cell| (cell.x, cell.y));
+        images.dedup();
+        images
+    }
+
+    /// Number of symmetric parts of the arena.
+    fn parts(&self) -> usize {
+        match self.symmetry {
+            Symmetry::Mirror | Symmetry::Rotational2 => 2,
+            Symmetry::Rotational4 => 4,
+        }
+    }
+
+    /// Whether all free cells can be reached from each other, moving between neighbours.
+    fn connected(&self) -> bool {
+        let Some(start) = self.cells().find(|&cell| !self.blocked(cell)) else {
+            return true;
+        };
+        let mut reached = vec![false; self.blocked.len()];
+        reached[self.index(start)] = true;
+        let mut queue = VecDeque::from([start]);
+        let mut count = 1;
+        while let Some(cell) = queue.pop_front() {
+            for step in [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y] {
+                let next = cell + step;
+                if !self.blocked(next) && !reached[self.index(next)] {
+                    reached[self.index(next)] = true;
+                    count += 1;
+                    queue.push_back(next);
+                }
+            }
+        }
+        count == self.blocked.iter().filter(|blocked| !**blocked).count()
+    }
+
+    /// Walls between the free and the blocked cells, facing the free ones.
+    /// Neighbouring edges in a line are merged into one wall.
+    fn walls(&self, origin: Vec2) -> Vec<Wall> {
+        let point = |cell: IVec2| (origin + cell.as_vec2() * CELL_SIZE).to_array();
+        let mut walls = vec![];
+        // horizontal walls along the rows, then vertical ones along the columns
+        for (along, across) in [(IVec2::X, IVec2::Y), (IVec2::Y, IVec2::X)] {
+            let lines = self.size.dot(across);
+            let length = self.size.dot(along);
+            // the outer walls are a separate shape
+            for line in 1..lines {
+                let mut start: Option<(i32, bool)> = None;
+                for position in 0..=length {
+                    let cell = along * position + across * line;
+                    // which side of the edge is free, none if both sides are the same
+                    let free_before = (position < length)
+                        .then(|| (self.blocked(cell), self.blocked(cell - across)))
+                        .filter(|(after, before)| after != before)
+                        .map(|(_, before)| !before);
+                    if start.map(|(_, free)| free) == free_before {
+                        continue;
+                    }
+                    if let Some((first, free)) = start {
+                        let normal = if free { -across } else { across };
+                        walls.push(Wall {
+                            from: point(along * first + across * line),
+                            to: point(cell),
+                            normal: Some(normal.as_vec2().to_array()),
+                        });
+                    }
+                    start = free_before.map(|free| (position, free));
+                }
+            }
+        }
+        walls
+    }
+}
+
+impl Generator {
+    /// Generate a map, the same settings and seed always give the same map.
+    /// All free space of the arena is connected, so every spawn can reach every other one.
+    /// Large or dense arenas get fewer obstacles and spawns, so that the map can still be sent
+    /// to the players whole.
+    pub fn generate(&self, seed: u64) -> Map {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut size = (self.size / CELL_SIZE)
+            .round()
+            .as_ivec2()
+            .max(IVec2::splat(3));
+        if self.symmetry == Symmetry::Rotational4 {
+            size = IVec2::splat(size.min_element());
+        }
+        let mut grid = Grid {
+            size,
+            symmetry: self.symmetry,
+            blocked: vec![false; (size.x * size.y) as usize],
+        };
+        let mut cells = grid.cells().collect::<Vec<_>>();
+
+        // spawns first, a whole set of corresponding cells at a time, apart from each other
+        cells.shuffle(&mut rng);
+        let mut spawns: Vec<IVec2> = vec![];
+        for &cell in &cells {
+            if spawns.len() >= usize::from(self.spawns) * grid.parts() {
+                break;
+            }
+            let images = grid.images(cell);
+            if spawns.len() + images.len() > MAX_SPAWNS {
+                break;
+            }
+            let apart = |cells: &[IVec2], other: IVec2| {
+                cells
+                    .iter()
+                    .all(|cell| (*cell - other).abs().max_element() >= SPAWN_SPACING)
+            };
+            if images.len() == grid.parts()
+                && images.iter().all(|&image| apart(&spawns, image))
+                && images
+                    .iter()
+                    .enumerate()
+                    .all(|(i, &image)| apart(&images[..i], image))
+            {
+                spawns.extend(images);
+            }
+        }
+
+        // then the obstacles, as long as they do not cut off part of the arena and their walls
+        // fit in a map change
+        let half = size.as_vec2() * CELL_SIZE / 2.0;
+        let origin = -half;
+        cells.shuffle(&mut rng);
+        let target = (self.density.clamp(0.0, 1.0) * cells.len() as f32) as usize;
+        let mut count = 0;
+        for &cell in &cells {
+            if count >= target {
+                break;
+            }
+            let images = grid.images(cell);
+            if images
+                .iter()
+                .any(|image| grid.blocked(*image) || spawns.contains(image))
+            {
+                continue;
+            }
+            images.iter().for_each(|&image| grid.set(image, true));
+            if grid.connected() && grid.walls(origin).len() <= MAX_OBSTACLE_WALLS {
+                count += images.len();
+            } else {
+                images.iter().for_each(|&image| grid.set(image, false));
+            }
+        }
+
+        let center = |cell: IVec2| origin + (cell.as_vec2() + 0.5) * CELL_SIZE;
+        Map {
+            version: MAP_VERSION,
+            name: format!("Generated {seed}"),
+            background_path: None,
+            walls: grid.walls(origin),
+            shapes: vec![Shape::Polygon {
+                polygon: vec![
+                    [-half.x, -half.y],
+                    [half.x, -half.y],
+                    [half.x, half.y],
+                    [-half.x, half.y],
+                ],
+                inward: true,
+            }],
+            spawns: spawns
+                .into_iter()
+                .map(|cell| Spawn::Position(center(cell).to_array()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::validation::validate;
+
+    #[test]
+    fn test_generated_maps_are_valid() {
+        for symmetry in [
+            Symmetry::Mirror,
+            Symmetry::Rotational2,
+            Symmetry::Rotational4,
+        ] {
+            for seed in 0..20 {
+                let generator = Generator {
+                    density: 0.4,
+                    symmetry,
+                    ..Default::default()
+                };
+                let map = generator.generate(seed);
+                let json = map.to_json();
+                let (parsed, problems) = validate(&json, Path::new(""), 12.0);
+                assert!(
+                    problems.is_empty(),
+                    "{symmetry:?} {seed}:\n{json}\n{problems:?}"
+                );
+                assert_eq!(parsed.unwrap().to_json(), json);
+
+                let parts = if symmetry == Symmetry::Rotational4 {
+                    4
+                } else {
+                    2
+                };
+                assert_eq!(map.spawns.len(), parts * 2);
+                assert_eq!(generator.generate(seed).to_json(), json);
+            }
+        }
+    }
+
+    #[test]
+    fn test_large_arena_fits() {
+        for symmetry in [Symmetry::Mirror, Symmetry::Rotational4] {
+            let generator = Generator {
+                size: Vec2::splat(3000.0),
+                density: 0.5,
+                symmetry,
+                spawns: u8::MAX,
+            };
+            let map = generator.generate(3);
+            assert!(map.segments().len() <= MAX_WALLS);
+            assert!(map.spawns.len() <= MAX_SPAWNS);
+            assert!(map.walls.len() > 100);
+            let (_, problems) = validate(&map.to_json(), Path::new(""), 12.0);
+            assert!(problems.is_empty(), "{symmetry:?}: {problems:?}");
+        }
+    }
+
+    #[test]
+    fn test_symmetric() {
+        let transforms: [(Symmetry, fn(Vec2) -> Vec2); 3] = [
+            (Symmetry::Mirror, |point| Vec2::new(-point.x, point.y)),
+            (Symmetry::Rotational2, |point| -point),
+            (Symmetry::Rotational4, |point| point.perp()),
+        ];
+        for (symmetry, transform) in transforms {
+            let generator = Generator {
+                symmetry,
+                ..Default::default()
+            };
+            let map = generator.generate(7);
+            let segments = map.segments();
+            assert!(segments.len() > 4);
+            for segment in &segments {
+                let (from, to) = (transform(segment.from), transform(segment.to));
+                assert!(
+                    segments.iter().any(|other| {
+                        let same =
+                            other.from.abs_diff_eq(from, 1e-3) && other.to.abs_diff_eq(to, 1e-3);
+                        let reversed =
+                            other.from.abs_diff_eq(to, 1e-3) && other.to.abs_diff_eq(from, 1e-3);
+                        same || reversed
+                    }),
+                    "{symmetry:?}: no image of {segment:?}"
+                );
+            }
+            for spawn in &map.spawns {
+                let image = transform(spawn.position());
+                assert!(
+                    map.spawns
+                        .iter()
+                        .any(|other| other.position().abs_diff_eq(image, 1e-3))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_walls_face_free_cells() {
+        let mut grid = Grid {
+            size: IVec2::new(3, 3),
+            symmetry: Symmetry::Mirror,
+            blocked: vec![false; 9],
+        };
+        grid.set(IVec2::new(1, 1), true);
+        grid.set(IVec2::new(2, 1), true);
+        let walls = grid.walls(Vec2::ZERO);
+        let walls = walls
+            .iter()
+            .map(|wall| (wall.from, wall.to, wall.normal.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            walls,
+            [
+                ([50.0, 50.0], [150.0, 50.0], [0.0, -1.0]),
+                ([50.0, 100.0], [150.0, 100.0], [0.0, 1.0]),
+                ([50.0, 50.0], [50.0, 100.0], [-1.0, 0.0]),
+            ]
+        );
+    }
+}
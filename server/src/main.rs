@@ -1,94 +1,279 @@
 // Allow dead code while we are still actively developing
 #![allow(dead_code, clippy::type_complexity)]
 
-use std::{fs::read_to_string, net::UdpSocket, path::Path};
+use std::{fs::write, net::UdpSocket, path::Path, process::ExitCode, time::Duration};
 
 use bevy::{
-    DefaultPlugins,
-    app::{App, FixedUpdate, Startup, Update},
-    ecs::schedule::{Condition, IntoScheduleConfigs},
-    input::{common_conditions::input_toggle_active, keyboard::KeyCode},
+    DefaultPlugins, MinimalPlugins,
+    app::{
+        App, AppExit, FixedUpdate, Last, PluginGroup, ScheduleRunnerPlugin, Startup,
+        TerminalCtrlCHandlerPlugin, Update,
+    },
+    ecs::schedule::{
+        Condition, IntoScheduleConfigs,
+        common_conditions::{on_event, resource_equals, resource_exists},
+    },
+    log::LogPlugin,
+    state::{
+        app::{AppExtStates, StatesPlugin},
+        condition::in_state,
+        state::{OnEnter, OnExit},
+    },
+    time::{Fixed, Time},
 };
+use rand::{SeedableRng, rngs::StdRng};
 
+/// Spatial grids limiting the collision tests to nearby objects.
+mod broadphase;
+/// Command line arguments of the server.
+mod cli;
+/// Swept collision tests.
+mod collision;
 /// Holds the server configuration struct.
 mod config;
 /// Helpful debugging methods to display various info during runtime.
 mod debug;
+/// Editing maps in the app.
+mod editor;
 /// Holds the [`Component`](bevy::ecs::component::Component)s for the server.
 mod entities;
+/// Events raised by the simulation.
+mod events;
+/// Procedurally generated symmetric maps.
+mod generator;
 /// The JSON representation of maps.
 mod map;
+/// Playing back recorded matches.
+mod playback;
+/// Recording matches to replay files.
+mod replay;
+/// The phases of a match and the rotation of its maps.
+mod rounds;
+/// Player statistics and the formulas ranking them.
+mod scoring;
 /// Game systems.
 mod systems;
+/// Teams the players can be divided into.
+mod teams;
 /// Show leaderboard.
 mod ui;
 /// Collection of useful functions.
 mod util;
+/// Checking map files for mistakes.
+mod validation;
 
+use broadphase::Broadphase;
+use clap::Parser;
+use cli::{Args, Command};
 use config::Config;
-use debug::{do_bounds, do_debug, do_normals, do_spawns, draw_bounds, draw_normals, draw_spawns};
-use entities::Socket;
+use debug::{Paused, debug_draw, draw_bounds, draw_normals, draw_spawns, toggle_pause};
+use editor::{
+    Editor, draw_editor, edit_map, setup_editor_status, show_edited_map, show_editor_status,
+};
+use entities::{GameRng, Socket};
+use events::{ControlReceived, GameEvent};
 use map::Maps;
+use playback::{
+    Playback, advance_playback, playback_controls, setup_playback_status, show_playback,
+    show_playback_status,
+};
+use replay::{Header, Recorder, Replay, finish_recording, record_tick};
+use rounds::{MatchState, RoundClock, advance_round, load_map, reset_stats, respawn_all};
+use scoring::{Departed, track_survival};
 use systems::{
-    apply_controls, bullet_bullet_collision, bullet_wall_collision, listen_socket, load_map,
-    move_bullets, move_tanks, move_turrets, player_disconnect, player_respawn, send_state,
-    setup_camera, shoot_countdown, tank_bullet_collision, tank_tank_collision,
+    Simulation, apply_controls, broadcast_map, bullet_bullet_collision, bullet_wall_collision,
+    disconnect_all, listen_socket, move_bullets, move_tanks, move_turrets, player_disconnect,
+    player_respawn, send_state, setup_camera, shoot_countdown, tank_bullet_collision,
+    tank_tank_collision, update_bullet_grid, update_tank_grid, update_wall_grid,
 };
 use ui::show_leaderboard;
+use validation::load_maps;
 
 use crate::ui::setup_leaderboard;
 
-fn main() {
-    // load config
-    let config: Config = serde_json::from_str(
-        &read_to_string("./assets/config.jsonc")
-            .unwrap_or_default()
-            .lines()
-            .map(|l| if l.trim().starts_with("//") { "" } else { l })
-            .collect::<Vec<_>>()
-            .join("\n"),
-    )
-    .inspect(|_| println!("correct format"))
-    .inspect_err(|err| println!("Incorrect config:\n{err}"))
-    .unwrap_or_default();
-    // bind socket
-    let socket = UdpSocket::bind(("127.0.0.1", 4000)).unwrap();
-    socket.set_nonblocking(true).unwrap();
+fn main() -> ExitCode {
+    let args = Args::parse();
+    // load config, the command line and environment take precedence over the file
+    let mut config = Config::load(&args.config);
+    config.apply_args(&args);
 
     let basedir = Path::new(&config.map_dir);
-    let maps = config
+    let map_paths = config
         .map_paths
         .iter()
-        .flat_map(|path| {
-            serde_json::from_str(
-                &read_to_string(basedir.join(path))
-                    .inspect_err(|err| println!("Map {path} not found:\n{err}"))
-                    .unwrap_or_default()
-                    .lines()
-                    .map(|l| if l.trim().starts_with("//") { "" } else { l })
-                    .collect::<Vec<_>>()
-                    .join("\n"),
-            )
-            .inspect_err(|err| println!("Reading map '{path}' failed with:\n{err}"))
-        })
+        .map(|path| basedir.join(path))
         .collect::<Vec<_>>();
-    assert!(!maps.is_empty(), "At least one map has to be loaded.");
+    match &args.command {
+        Some(Command::ValidateMaps { paths }) => {
+            let paths = if paths.is_empty() { &map_paths } else { paths };
+            let (_, errors) = load_maps(paths, &config);
+            println!("Checked {} maps, found {errors} errors.", paths.len());
+            return if errors == 0 {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            };
+        }
+        Some(Command::GenerateMap(generator_args)) => {
+            let mut generator = config.map_generator.clone().unwrap_or_default();
+            generator.apply_args(generator_args);
+            let map = generator
+                .generate(generator_args.seed.unwrap_or_else(rand::random))
+                .to_json();
+            match &generator_args.output {
+                Some(path) => {
+                    if let Err(err) = write(path, map) {
+                        eprintln!("Could not write map {}:\n{err}", path.display());
+                        return ExitCode::FAILURE;
+                    }
+                }
+                None => print!("{map}"),
+            }
+            return ExitCode::SUCCESS;
+        }
+        None => {}
+    }
+
+    if let Some(path) = &args.edit {
+        let editor = Editor::open(path)
+            .unwrap_or_else(|err| panic!("Could not open map {}:\n{err}", path.display()));
+        App::new()
+            .insert_resource(config)
+            .insert_resource(editor)
+            .add_systems(Startup, (setup_camera, setup_editor_status))
+            .add_systems(
+                Update,
+                (
+                    edit_map,
+                    show_edited_map,
+                    (draw_normals, draw_bounds, draw_spawns, draw_editor),
+                    show_editor_status,
+                )
+                    .chain(),
+            )
+            .add_plugins(DefaultPlugins)
+            .run();
+        return ExitCode::SUCCESS;
+    }
+
+    println!("Effective configuration:\n{config:#?}");
+    // generated maps replace the map files
+    let maps = match config.map_generator {
+        Some(_) => vec![],
+        None => load_maps(&map_paths, &config).0,
+    };
+
+    // a replay only uses the map files for their backgrounds, it can do without them
+    if let Some(path) = &args.replay {
+        let replay = Replay::load(path)
+            .unwrap_or_else(|err| panic!("Could not load replay {}:\n{err}", path.display()));
+        App::new()
+            .insert_resource(config)
+            .init_resource::<Departed>()
+            .insert_resource(Maps {
+                loaded: maps,
+                current: None,
+            })
+            .insert_resource(Playback::new(replay))
+            .add_systems(
+                Startup,
+                (setup_camera, setup_leaderboard, setup_playback_status),
+            )
+            .add_systems(
+                Update,
+                (
+                    playback_controls,
+                    advance_playback,
+                    show_playback,
+                    show_leaderboard,
+                    show_playback_status,
+                )
+                    .chain(),
+            )
+            .add_plugins((DefaultPlugins, debug_draw))
+            .run();
+        return ExitCode::SUCCESS;
+    }
 
-    App::new()
-        .insert_resource(Socket(socket))
+    assert!(
+        !maps.is_empty() || config.map_generator.is_some(),
+        "At least one map has to be loaded."
+    );
+
+    // bind socket
+    let socket = UdpSocket::bind((config.bind_address, config.port)).unwrap();
+    socket.set_nonblocking(true).unwrap();
+
+    let seed = config.seed.unwrap_or_else(rand::random);
+    println!("Using seed {seed}");
+
+    let headless = config.headless;
+    let tick_rate = config.tick_rate;
+    let record_path = config.record_path.clone();
+    let parameters = config.parameters();
+    let warmup = config.rounds.warmup;
+    let mut app = App::new();
+    // plugins come first, the states below need the `StatesPlugin`
+    if headless {
+        app.add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1.0 / tick_rate,
+            ))),
+            StatesPlugin,
+            LogPlugin::default(),
+            TerminalCtrlCHandlerPlugin,
+        ));
+    } else {
+        app.add_plugins((DefaultPlugins, debug_draw))
+            .add_systems(Startup, (setup_camera, setup_leaderboard))
+            .add_systems(FixedUpdate, show_leaderboard)
+            .add_systems(Update, toggle_pause);
+    }
+
+    app.insert_resource(Socket(socket))
         .insert_resource(config)
+        .insert_resource(GameRng(StdRng::seed_from_u64(seed)))
+        .insert_resource(Time::<Fixed>::from_hz(tick_rate))
+        .init_resource::<Departed>()
+        .init_resource::<Paused>()
+        .init_resource::<Broadphase>()
+        .init_state::<MatchState>()
+        .insert_resource(RoundClock {
+            remaining: warmup,
+            played: 0,
+        })
+        .add_event::<GameEvent>()
+        .add_event::<ControlReceived>()
         .insert_resource(Maps {
             loaded: maps,
             current: None,
         })
-        .add_systems(Startup, (setup_camera, load_map, setup_leaderboard))
-        .add_systems(FixedUpdate, (listen_socket, show_leaderboard))
+        .configure_sets(
+            FixedUpdate,
+            Simulation
+                .run_if(resource_equals(Paused(false)))
+                .run_if(in_state(MatchState::Warmup).or(in_state(MatchState::Playing))),
+        )
+        .add_systems(Startup, load_map)
+        .add_systems(OnExit(MatchState::Warmup), reset_stats)
+        .add_systems(OnExit(MatchState::RoundOver), load_map)
+        .add_systems(OnEnter(MatchState::Countdown), respawn_all)
+        .add_systems(Update, broadcast_map)
         .add_systems(
-            Update,
+            FixedUpdate,
             (
+                listen_socket,
+                advance_round.run_if(resource_equals(Paused(false))),
                 (
+                    update_wall_grid,
                     apply_controls,
-                    (move_tanks, move_turrets, move_bullets),
+                    (
+                        (move_tanks, move_turrets),
+                        update_tank_grid,
+                        move_bullets,
+                        update_bullet_grid,
+                    )
+                        .chain(),
                     (
                         tank_bullet_collision,
                         tank_tank_collision,
@@ -96,16 +281,44 @@ fn main() {
                         bullet_wall_collision,
                     )
                         .chain(),
-                    (player_respawn, shoot_countdown, player_disconnect),
+                    (
+                        player_respawn,
+                        shoot_countdown,
+                        track_survival,
+                        player_disconnect,
+                    ),
                 )
-                    .run_if(input_toggle_active(false, KeyCode::Space)),
+                    .chain()
+                    .in_set(Simulation),
                 send_state,
+                // every tick is recorded, also while the simulation is paused or between rounds
+                record_tick.run_if(resource_exists::<Recorder>),
             )
                 .chain(),
         )
-        .add_systems(Update, draw_normals.run_if(do_debug.or(do_normals)))
-        .add_systems(Update, draw_bounds.run_if(do_debug.or(do_bounds)))
-        .add_systems(Update, draw_spawns.run_if(do_debug.or(do_spawns)))
-        .add_plugins(DefaultPlugins)
-        .run();
+        .add_systems(Last, disconnect_all.run_if(on_event::<AppExit>))
+        .add_systems(
+            Last,
+            finish_recording
+                .run_if(resource_exists::<Recorder>)
+                .run_if(on_event::<AppExit>),
+        );
+
+    if let Some(path) = &record_path {
+        let header = Header {
+            tick_rate,
+            seed,
+            parameters,
+        };
+        match Recorder::create(path, &header) {
+            Ok(recorder) => {
+                println!("Recording the match to {}", path.display());
+                app.insert_resource(recorder);
+            }
+            Err(err) => println!("Could not create replay {}:\n{err}", path.display()),
+        }
+    }
+
+    app.run();
+    ExitCode::SUCCESS
 }
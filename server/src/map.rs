@@ -1,59 +1,345 @@
 use bevy::{
-    asset::AssetServer, ecs::{component::Component, resource::Resource, system::{Commands, Res}}, log, math::{Dir2, Vec2}, sprite::Sprite, transform::components::Transform
+    asset::AssetServer,
+    color::Color,
+    ecs::{
+        component::Component,
+        resource::Resource,
+        system::{Commands, Res},
+    },
+    log,
+    math::{Dir2, Vec2},
+    sprite::Sprite,
+    transform::components::Transform,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, de::Error};
 
 use crate::{
-    entities::{self, Spawn},
-    util::{with_z, with_z_arr},
+    entities,
+    util::{load_sprite, with_z},
 };
 
-#[derive(Deserialize)]
-struct Wall {
-    from: [f32; 2],
-    to: [f32; 2],
-    normal: [f32; 2],
+/// Newest version of the map format.
+/// - 1: walls are listed one segment at a time, each with its normal
+/// - 2: adds `shapes`, and the normal of a wall segment may be left out
+pub const MAP_VERSION: u8 = 2;
+
+/// Number of segments a circle is made of, unless given in the map.
+const CIRCLE_SEGMENTS: u8 = 16;
+/// Color of the floor drawn for maps without a background image.
+const FLOOR_COLOR: Color = Color::srgb(0.33, 0.32, 0.28);
+
+/// A plain floor covering the bounding box of `points`, below everything else of the map.
+/// Drawn for maps without a background image.
+pub fn floor(points: impl IntoIterator<Item = Vec2>) -> Option<(Sprite, Transform)> {
+    let (min, max) = points
+        .into_iter()
+        .fold((Vec2::INFINITY, Vec2::NEG_INFINITY), |(min, max), point| {
+            (min.min(point), max.max(point))
+        });
+    let size = max - min;
+    size.cmpgt(Vec2::ZERO).all().then(|| {
+        let center = with_z((min + max) / 2.0, -1.0);
+        (
+            Sprite::from_color(FLOOR_COLOR, size),
+            Transform::from_translation(center),
+        )
+    })
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub(crate) struct Wall {
+    pub(crate) from: [f32; 2],
+    pub(crate) to: [f32; 2],
+    /// Defaults to the left of the direction from `from` to `to`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) normal: Option<[f32; 2]>,
+}
+
+impl Wall {
+    /// The segment of the wall, none if it has no length.
+    pub(crate) fn segment(&self) -> Option<Segment> {
+        let from: Vec2 = self.from.into();
+        let to: Vec2 = self.to.into();
+        let direction = Dir2::new(to - from).ok()?;
+        let normal = self
+            .normal
+            .and_then(|[x, y]| Dir2::from_xy(x, y).ok())
+            .unwrap_or(Dir2::new_unchecked(
+                direction.rotate(Vec2 { x: 0.0, y: 1.0 }),
+            ));
+        Some(Segment { from, to, normal })
+    }
+}
+
+/// Walls given as a shape, their normals are derived from it.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub(crate) enum Shape {
+    /// Closed polygon, its normals point outwards.
+    /// With `inward` they point inwards instead, as for the outer walls of the map.
+    Polygon {
+        polygon: Vec<[f32; 2]>,
+        #[serde(default)]
+        inward: bool,
+    },
+    /// Open chain of free-standing walls, solid from both sides.
+    Polyline { polyline: Vec<[f32; 2]> },
+    /// Circle approximated by a regular polygon of `segments` sides.
+    Circle {
+        circle: [f32; 2],
+        radius: f32,
+        #[serde(default = "circle_segments")]
+        segments: u8,
+        #[serde(default)]
+        inward: bool,
+    },
+}
+
+fn circle_segments() -> u8 {
+    CIRCLE_SEGMENTS
+}
+
+/// A straight piece of wall, as the walls and shapes of a map are spawned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Segment {
+    pub from: Vec2,
+    pub to: Vec2,
+    pub normal: Dir2,
+}
+
+/// Segments of the closed polygon through `points`, with normals pointing outwards, or
+/// inwards if `inward` is set.
+fn closed_segments(points: &[Vec2], inward: bool) -> Vec<Segment> {
+    let edges = || {
+        points
+            .iter()
+            .copied()
+            .zip(points.iter().copied().cycle().skip(1))
+    };
+    // shoelace formula, positive for counter-clockwise polygons, whose inside is on the left
+    let area: f32 = edges().map(|(from, to)| from.perp_dot(to)).sum();
+    edges()
+        .filter_map(|(from, to)| {
+            let left = Dir2::new((to - from).perp()).ok()?;
+            let normal = if (area > 0.0) != inward { -left } else { left };
+            Some(Segment { from, to, normal })
+        })
+        .collect()
+}
+
+impl Shape {
+    pub(crate) fn segments(&self) -> Vec<Segment> {
+        match self {
+            Shape::Polygon { polygon, inward } => {
+                let points = polygon
+                    .iter()
+                    .map(|&point| point.into())
+                    .collect::<Vec<_>>();
+                closed_segments(&points, *inward)
+            }
+            Shape::Polyline { polyline } => polyline
+                .windows(2)
+                .filter_map(|pair| {
+                    let (from, to) = (Vec2::from(pair[0]), Vec2::from(pair[1]));
+                    let left = Dir2::new((to - from).perp()).ok()?;
+                    Some([
+                        Segment {
+                            from,
+                            to,
+                            normal: left,
+                        },
+                        Segment {
+                            from,
+                            to,
+                            normal: -left,
+                        },
+                    ])
+                })
+                .flatten()
+                .collect(),
+            Shape::Circle {
+                circle,
+                radius,
+                segments,
+                inward,
+            } => {
+                let points = (0..*segments)
+                    .map(|i| {
+                        let angle = std::f32::consts::TAU * f32::from(i) / f32::from(*segments);
+                        Vec2::from(*circle) + *radius * Vec2::from_angle(angle)
+                    })
+                    .collect::<Vec<_>>();
+                closed_segments(&points, *inward)
+            }
+        }
+    }
+}
+
+/// A spawn point, either just its position or reserved for a team.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub(crate) enum Spawn {
+    Position([f32; 2]),
+    Team { position: [f32; 2], team: u8 },
+}
+
+impl Spawn {
+    pub(crate) fn position(&self) -> Vec2 {
+        match *self {
+            Spawn::Position(position) | Spawn::Team { position, .. } => position.into(),
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct Map {
-    name: String,
-    background_path: String,
-    walls: Vec<Wall>,
-    spawns: Vec<[f32; 2]>,
+    /// Version of the map format, see [`MAP_VERSION`].
+    #[serde(default = "first_version")]
+    pub(crate) version: u8,
+    pub(crate) name: String,
+    /// Image shown below the walls, relative to the assets directory.
+    #[serde(default)]
+    pub(crate) background_path: Option<String>,
+    #[serde(default)]
+    pub(crate) walls: Vec<Wall>,
+    #[serde(default)]
+    pub(crate) shapes: Vec<Shape>,
+    pub(crate) spawns: Vec<Spawn>,
+}
+
+fn first_version() -> u8 {
+    1
+}
+
+/// Blank out the `//` comments of a JSON file.
+/// Everything else keeps its line and column, so that errors point into the original file.
+pub fn strip_comments(json: &str) -> String {
+    json.lines()
+        .map(|line| {
+            let (mut in_string, mut escaped) = (false, false);
+            let mut chars = line.char_indices().peekable();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' if in_string => escaped = true,
+                    '"' => in_string = !in_string,
+                    '/' if !in_string && chars.peek().is_some_and(|&(_, c)| c == '/') => {
+                        return &line[..i];
+                    }
+                    _ => {}
+                }
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 impl Map {
-    pub fn spawn(&self, mut commands: Commands, asset_server: Res<AssetServer>) {
+    /// Parse a map from its JSON, allowing `//` comments.
+    pub fn parse(json: &str) -> serde_json::Result<Self> {
+        let map: Self = serde_json::from_str(&strip_comments(json))?;
+        if map.version > MAP_VERSION {
+            return Err(serde_json::Error::custom(format!(
+                "map version {} is newer than the supported version {MAP_VERSION}",
+                map.version
+            )));
+        }
+        if map.version < 2 && !map.shapes.is_empty() {
+            return Err(serde_json::Error::custom("shapes need map version 2"));
+        }
+        Ok(map)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// All wall segments of the map, the listed walls followed by the expanded shapes.
+    pub fn segments(&self) -> Vec<Segment> {
+        let walls = self.walls.iter().filter_map(|wall| {
+            let segment = wall.segment();
+            if segment.is_none() {
+                log::warn!("The map \"{}\" contains 0 length walls.", self.name);
+            }
+            segment
+        });
+        walls
+            .chain(self.shapes.iter().flat_map(Shape::segments))
+            .collect()
+    }
+
+    /// The JSON of the map, laid out like the hand-written maps with one wall, shape or spawn
+    /// per line.
+    pub fn to_json(&self) -> String {
+        fn list(items: &[impl Serialize]) -> String {
+            if items.is_empty() {
+                return "[]".into();
+            }
+            let lines = items
+                .iter()
+                .map(|item| {
+                    format!(
+                        "        {}",
+                        serde_json::to_string(item).unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<_>>();
+            format!("[\n{}\n    ]", lines.join(",\n"))
+        }
+        let mut json = format!(
+            "{{\n    \"version\": {},\n    \"name\": {},\n",
+            self.version,
+            serde_json::json!(self.name)
+        );
+        if let Some(path) = &self.background_path {
+            json += &format!("    \"background_path\": {},\n", serde_json::json!(path));
+        }
+        json += &format!(
+            "    \"walls\": {},\n    \"shapes\": {},\n    \"spawns\": {}\n}}\n",
+            list(&self.walls),
+            list(&self.shapes),
+            list(&self.spawns)
+        );
+        json
+    }
+
+    pub fn spawn(&self, mut commands: Commands, asset_server: Option<Res<AssetServer>>) {
         commands
             .spawn((
-                entities::Map {},
-                Sprite::from_image(asset_server.load(&self.background_path)),
+                entities::Map {
+                    name: self.name.clone(),
+                },
+                self.background_path
+                    .as_ref()
+                    .map_or(Sprite::default(), |path| load_sprite(&asset_server, path)),
             ))
             .with_children(|parent| {
-                for wall in &self.walls {
-                    let from: Vec2 = wall.from.into();
-                    let to: Vec2 = wall.to.into();
-                    let position = with_z((from + to) / 2.0, 0.0);
+                let segments = self.segments();
+                let ends = segments.iter().flat_map(|wall| [wall.from, wall.to]);
+                if self.background_path.is_none()
+                    && let Some(floor) = floor(ends)
+                {
+                    parent.spawn(floor);
+                }
+                for Segment { from, to, normal } in segments {
                     if let Ok((direction, length)) = Dir2::new_and_length(to - from) {
-                        let normal = Dir2::from_xy(wall.normal[0], wall.normal[1]).unwrap_or(
-                            Dir2::new_unchecked(direction.rotate(Vec2 { x: 0.0, y: 1.0 })),
-                        );
-                        parent.spawn((
-                            entities::Wall {
-                                normal,
-                                direction,
-                                half_length: length / 2.0,
-                            },
-                            Transform::from_translation(position),
-                        ));
-                    } else {
-                        log::warn!("The current map \"{}\" contains 0 length walls.", self.name);
+                        let wall = entities::Wall {
+                            normal,
+                            direction,
+                            half_length: length / 2.0,
+                        };
+                        let display = wall.display((from + to) / 2.0, &asset_server);
+                        parent.spawn((wall, display));
                     }
                 }
-                for &spawn in &self.spawns {
-                    let pos = with_z_arr(spawn, 0.0);
-                    parent.spawn((Spawn(), Transform::from_translation(pos)));
+                for spawn in &self.spawns {
+                    let team = match *spawn {
+                        Spawn::Position(_) => None,
+                        Spawn::Team { team, .. } => Some(team),
+                    };
+                    let pos = with_z(spawn.position(), 0.0);
+                    parent.spawn((entities::Spawn(team), Transform::from_translation(pos)));
                 }
             });
     }
@@ -64,3 +350,107 @@ pub struct Maps {
     pub(crate) loaded: Vec<Map>,
     pub(crate) current: Option<usize>,
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::Vec3;
+
+    use super::*;
+
+    fn shape(json: &str) -> Vec<Segment> {
+        serde_json::from_str::<Shape>(json).unwrap().segments()
+    }
+
+    #[test]
+    fn test_polygon_normals() {
+        let square = "[[0, 0], [10, 0], [10, 10], [0, 10]]";
+        let reversed = "[[0, 10], [10, 10], [10, 0], [0, 0]]";
+        for points in [square, reversed] {
+            let outward = shape(&format!(r#"{{ "polygon": {points} }}"#));
+            let inward = shape(&format!(r#"{{ "polygon": {points}, "inward": true }}"#));
+            assert_eq!(outward.len(), 4);
+            for (outward, inward) in outward.iter().zip(&inward) {
+                let middle = (outward.from + outward.to) / 2.0;
+                assert!((middle - Vec2::splat(5.0)).dot(*outward.normal) > 0.0);
+                assert_eq!(inward.normal, -outward.normal);
+            }
+        }
+    }
+
+    #[test]
+    fn test_polyline_two_sided() {
+        let segments = shape(r#"{ "polyline": [[0, 0], [10, 0], [10, 10]] }"#);
+        assert_eq!(segments.len(), 4);
+        assert_eq!(segments[0].normal, -segments[1].normal);
+        assert_eq!(segments[2].normal, -segments[3].normal);
+        assert_eq!(segments[2].from, Vec2::new(10.0, 0.0));
+    }
+
+    #[test]
+    fn test_circle() {
+        let segments = shape(r#"{ "circle": [5, 5], "radius": 10, "segments": 8 }"#);
+        assert_eq!(segments.len(), 8);
+        for segment in segments {
+            assert!((segment.from - Vec2::splat(5.0)).length() - 10.0 < 1e-4);
+            let middle = (segment.from + segment.to) / 2.0;
+            assert!((middle - Vec2::splat(5.0)).dot(*segment.normal) > 0.0);
+        }
+        assert_eq!(shape(r#"{ "circle": [0, 0], "radius": 1 }"#).len(), 16);
+    }
+
+    #[test]
+    fn test_floor() {
+        let (sprite, transform) = floor([Vec2::new(-50.0, 10.0), Vec2::new(150.0, -30.0)]).unwrap();
+        assert_eq!(sprite.custom_size, Some(Vec2::new(200.0, 40.0)));
+        assert_eq!(transform.translation, Vec3::new(50.0, -10.0, -1.0));
+        assert!(floor([Vec2::ZERO, Vec2::X]).is_none());
+    }
+
+    #[test]
+    fn test_walls_drawn_behind_face() {
+        let map = Map::parse(include_str!("../assets/maps/layout-00.jsonc")).unwrap();
+        for Segment { from, to, normal } in map.segments() {
+            let (direction, length) = Dir2::new_and_length(to - from).unwrap();
+            let wall = entities::Wall {
+                normal,
+                direction,
+                half_length: length / 2.0,
+            };
+            let center = (from + to) / 2.0;
+            let (transform, sprite) = wall.display(center, &None);
+            let size = sprite.custom_size.unwrap();
+            assert_eq!(size, Vec2::new(length, entities::WALL_THICKNESS));
+            let middle = transform.transform_point((-sprite.anchor.as_vec() * size).extend(0.0));
+            let offset = middle.truncate() - center;
+            assert!((offset.dot(*normal) + entities::WALL_THICKNESS / 2.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_versions() {
+        let v1 = Map::parse(include_str!("../assets/maps/layout-00.jsonc")).unwrap();
+        assert_eq!(v1.version, 1);
+        assert!(
+            v1.segments()
+                .iter()
+                .all(|segment| segment.from != segment.to)
+        );
+        let v2 = Map::parse(include_str!("../assets/maps/map.jsonc")).unwrap();
+        assert_eq!(v2.segments().len(), 4);
+        assert!(
+            v2.segments()
+                .iter()
+                .all(|segment| segment.normal.dot(-segment.from) > 0.0)
+        );
+
+        let map = |version: u8| {
+            format!(
+                r#"{{ "version": {version}, "name": "", "background_path": "", "spawns": [],
+                "shapes": [{{ "polyline": [[0, 0], [1, 0]] }}] }}"#
+            )
+        };
+        assert!(Map::parse(&map(1)).is_err());
+        assert!(Map::parse(&map(2)).is_ok());
+        assert!(Map::parse(&map(MAP_VERSION + 1)).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+use std::{collections::HashSet, time::Duration};
+
+use bevy::{
+    asset::AssetServer,
+    color::Color,
+    ecs::{
+        component::Component,
+        entity::Entity,
+        query::With,
+        resource::Resource,
+        system::{Commands, Query, Res, ResMut},
+    },
+    input::{ButtonInput, keyboard::KeyCode},
+    math::{Dir2, Quat, Vec2, Vec3},
+    render::view::Visibility,
+    sprite::Sprite,
+    time::Time,
+    transform::components::Transform,
+    ui::{BackgroundColor, Node, PositionType, UiRect, Val, widget::Text},
+};
+use messages::server;
+
+use crate::{
+    config::Config,
+    entities::{self, Bullet, Player, Spawn, Tank, Turret, Wall},
+    map::{Maps, floor},
+    replay::Replay,
+    scoring::{Departed, Stats},
+    teams::Team,
+    util::{load_sprite, with_z_arr},
+};
+
+/// Slowest playback speed.
+const MIN_SPEED: f64 = 0.25;
+/// Fastest playback speed.
+const MAX_SPEED: f64 = 8.0;
+/// Seconds skipped when seeking with the arrow keys.
+const SEEK_SECS: f64 = 5.0;
+
+/// A replay being played back.
+#[derive(Resource)]
+pub struct Playback {
+    replay: Replay,
+    /// Current position in the replay, in ticks.
+    position: f64,
+    /// Factor of the recorded tick rate at which the replay advances.
+    speed: f64,
+    paused: bool,
+    /// Index of the frame that is currently displayed.
+    shown: Option<usize>,
+    /// Index into the replay's maps of the map that is currently displayed.
+    shown_map: Option<usize>,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            position: 0.0,
+            speed: 1.0,
+            paused: false,
+            shown: None,
+            shown_map: None,
+        }
+    }
+
+    /// Position of the last recorded frame.
+    fn end(&self) -> f64 {
+        self.replay.frames.len().saturating_sub(1) as f64
+    }
+
+    fn seek(&mut self, position: f64) {
+        self.position = position.clamp(0.0, self.end());
+    }
+
+    fn name(&self, id: u32) -> String {
+        self.replay
+            .names
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| format!("Player {id}"))
+    }
+}
+
+/// Marker for the text showing the position and speed of the playback.
+#[derive(Component)]
+#[require(Text)]
+pub struct PlaybackStatus;
+
+pub fn setup_playback_status(mut commands: Commands) {
+    commands.spawn((
+        PlaybackStatus,
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(4.0),
+            bottom: Val::Px(4.0),
+            padding: UiRect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
+    ));
+}
+
+/// Keyboard controls of the playback:
+/// - space: pause / resume
+/// - `,` / `.`: step one tick back / forward
+/// - left / right: seek [`SEEK_SECS`] back / forward
+/// - home / end, `0`-`9`: seek to the start / end, to 0% - 90% of the replay
+/// - down / up: halve / double the speed
+pub fn playback_controls(keys: Res<ButtonInput<KeyCode>>, mut playback: ResMut<Playback>) {
+    if keys.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+
+    let position = playback.position.floor();
+    if keys.just_pressed(KeyCode::Period) {
+        playback.paused = true;
+        playback.seek(position + 1.0);
+    }
+    if keys.just_pressed(KeyCode::Comma) {
+        playback.paused = true;
+        playback.seek(position - 1.0);
+    }
+
+    let seek = SEEK_SECS * playback.replay.header.tick_rate;
+    if keys.just_pressed(KeyCode::ArrowRight) {
+        playback.seek(position + seek);
+    }
+    if keys.just_pressed(KeyCode::ArrowLeft) {
+        playback.seek(position - seek);
+    }
+    if keys.just_pressed(KeyCode::Home) {
+        playback.seek(0.0);
+    }
+    if keys.just_pressed(KeyCode::End) {
+        let end = playback.end();
+        playback.seek(end);
+    }
+    let digits = [
+        KeyCode::Digit0,
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    if let Some(tenths) = digits.iter().position(|&key| keys.just_pressed(key)) {
+        let end = playback.end();
+        playback.seek((end * tenths as f64 / 10.0).floor());
+    }
+
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        playback.speed = (playback.speed * 2.0).min(MAX_SPEED);
+    }
+    if keys.just_pressed(KeyCode::ArrowDown) {
+        playback.speed = (playback.speed / 2.0).max(MIN_SPEED);
+    }
+}
+
+pub fn advance_playback(time: Res<Time>, mut playback: ResMut<Playback>) {
+    if playback.paused {
+        return;
+    }
+    let position = playback.position
+        + time.delta_secs_f64() * playback.replay.header.tick_rate * playback.speed;
+    if position >= playback.end() {
+        playback.paused = true;
+    }
+    playback.seek(position);
+}
+
+/// Rotation of a sprite pointing up towards `direction`.
+fn rotation(direction: [f32; 2]) -> Quat {
+    Quat::from_rotation_z(Vec2::Y.angle_to(direction.into()))
+}
+
+/// Spawn a map as recorded in the replay, when its file is not loaded.
+/// Without the file there is no background image, a plain floor is drawn instead.
+fn spawn_recorded_map(
+    commands: &mut Commands,
+    asset_server: &Option<Res<AssetServer>>,
+    map: &server::Map,
+) {
+    commands
+        .spawn(entities::Map {
+            name: map.name.clone(),
+        })
+        .with_children(|parent| {
+            let ends = map.walls.iter().flat_map(|wall| {
+                let origin = Vec2::from(wall.origin);
+                [origin, origin + Vec2::from(wall.direction_length)]
+            });
+            if let Some(floor) = floor(ends) {
+                parent.spawn(floor);
+            }
+            for wall in &map.walls {
+                let direction_length = Vec2::from(wall.direction_length);
+                if let (Ok((direction, length)), Ok(normal)) = (
+                    Dir2::new_and_length(direction_length),
+                    Dir2::new(wall.normal.into()),
+                ) {
+                    let wall_entity = Wall {
+                        normal,
+                        direction,
+                        half_length: length / 2.0,
+                    };
+                    let display = wall_entity.display(
+                        Vec2::from(wall.origin) + direction_length / 2.0,
+                        asset_server,
+                    );
+                    parent.spawn((wall_entity, display));
+                }
+            }
+            for &spawn in &map.spawns {
+                parent.spawn((
+                    Spawn(None),
+                    Transform::from_translation(with_z_arr(spawn, 0.0)),
+                ));
+            }
+        });
+}
+
+/// Replace the displayed world with the frame at the current position of the playback.
+#[allow(clippy::too_many_arguments)]
+pub fn show_playback(
+    mut commands: Commands,
+    asset_server: Option<Res<AssetServer>>,
+    config: Res<Config>,
+    mut playback: ResMut<Playback>,
+    maps: Res<Maps>,
+    mut departed: ResMut<Departed>,
+    players: Query<Entity, With<Player>>,
+    bullets: Query<Entity, With<Bullet>>,
+    shown_maps: Query<Entity, With<entities::Map>>,
+) {
+    let index = playback.position as usize;
+    if playback.replay.frames.is_empty() || playback.shown == Some(index) {
+        return;
+    }
+    playback.shown = Some(index);
+
+    players
+        .iter()
+        .chain(bullets.iter())
+        .for_each(|entity| commands.entity(entity).despawn());
+
+    let frame = &playback.replay.frames[index];
+    let parameters = playback.replay.header.parameters;
+    for player in &frame.players {
+        let replayed = Player::replayed(
+            player.id,
+            playback.name(player.id),
+            player.team,
+            player.kills,
+            player.deaths,
+            // a corrupt replay may hold any value, it must not crash the viewer
+            Duration::try_from_secs_f32(player.survival_time).unwrap_or_default(),
+        );
+        let color = player
+            .team
+            .and_then(|team| config.teams.get(usize::from(team)))
+            .map_or(Color::WHITE, Team::color);
+        let tank = frame.tanks.iter().find(|tank| tank.id == player.id);
+        commands
+            .spawn((replayed, Transform::default(), Visibility::Visible))
+            .with_children(|parent| {
+                let Some(tank) = tank else {
+                    return;
+                };
+                let tank_rotation = rotation(tank.tank_direction);
+                // the turret rotates around a pivot below its center, as in `move_turrets`
+                let mut turret = Transform::from_rotation(
+                    tank_rotation.inverse() * rotation(tank.turret_direction),
+                );
+                turret.translation = Vec3::new(0.0, -3.5, 0.0) + 6.5 * turret.up().as_vec3();
+                parent
+                    .spawn((
+                        Tank {
+                            radius: parameters.tank_radius,
+                            health: tank.health,
+                            ..Default::default()
+                        },
+                        Transform::from_translation(with_z_arr(tank.position, 0.0))
+                            .with_rotation(tank_rotation),
+                        Sprite {
+                            color,
+                            ..load_sprite(&asset_server, "tank_body.png")
+                        },
+                    ))
+                    .with_child((
+                        Turret::default(),
+                        turret,
+                        Sprite {
+                            color,
+                            ..load_sprite(&asset_server, "tank_turret.png")
+                        },
+                    ));
+            });
+    }
+    for bullet in &frame.bullets {
+        commands.spawn((
+            Bullet::new(
+                Entity::PLACEHOLDER,
+                bullet.direction.into(),
+                parameters.bullet_radius,
+                parameters.bullet_max_bounces,
+                0.0,
+            ),
+            Transform::from_translation(with_z_arr(bullet.position, 0.0)),
+            load_sprite(&asset_server, "bullet.png"),
+        ));
+    }
+
+    // players that left before this frame keep their last recorded statistics
+    let mut seen: HashSet<_> = frame.players.iter().map(|player| player.id).collect();
+    departed.0 = playback.replay.frames[..index]
+        .iter()
+        .rev()
+        .flat_map(|frame| &frame.players)
+        .filter(|player| seen.insert(player.id))
+        .map(|player| Stats {
+            name: playback.name(player.id),
+            team: player.team,
+            kills: player.kills,
+            deaths: player.deaths,
+            survival_time: Duration::try_from_secs_f32(player.survival_time).unwrap_or_default(),
+            connected: false,
+        })
+        .collect();
+
+    let map = playback
+        .replay
+        .maps
+        .iter()
+        .rposition(|(start, _)| *start <= index);
+    if map != playback.shown_map {
+        playback.shown_map = map;
+        shown_maps
+            .iter()
+            .for_each(|entity| commands.entity(entity).despawn());
+        if let Some(map) = map {
+            let recorded = &playback.replay.maps[map].1;
+            match maps.loaded.iter().find(|map| map.name() == recorded.name) {
+                Some(map) => map.spawn(commands, asset_server),
+                None => spawn_recorded_map(&mut commands, &asset_server, recorded),
+            }
+        }
+    }
+}
+
+pub fn show_playback_status(
+    playback: Res<Playback>,
+    mut status: Query<&mut Text, With<PlaybackStatus>>,
+) {
+    let tick_rate = playback.replay.header.tick_rate;
+    // the phase of the match, and whether the recorded game was paused
+    let recorded = playback
+        .replay
+        .frames
+        .get(playback.position as usize)
+        .map(|frame| {
+            let paused = if frame.paused { ", paused" } else { "" };
+            format!("  ({:?}{paused})", frame.state)
+        })
+        .unwrap_or_default();
+    for mut text in &mut status {
+        text.0 = format!(
+            "{:.1}s / {:.1}s  {}x{}{recorded}",
+            playback.position / tick_rate,
+            playback.end() / tick_rate,
+            playback.speed,
+            if playback.paused { "  paused" } else { "" }
+        );
+    }
+}
@@ -0,0 +1,558 @@
+use std::{
+    collections::HashMap,
+    fs::{File, read},
+    io::{self, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use bevy::{
+    ecs::{
+        event::EventReader,
+        hierarchy::{ChildOf, Children},
+        query::{Added, With, Without},
+        resource::Resource,
+        system::{Query, Res, ResMut},
+    },
+    log,
+    state::state::State,
+    transform::components::Transform,
+};
+use messages::server::{self, Parameters};
+
+use crate::{
+    debug::Paused,
+    entities::{self, Bullet, Player, Spawn, Tank, Turret, Wall},
+    events::{ControlReceived, GameEvent},
+    rounds::MatchState,
+    systems::{map_change, world_state},
+};
+
+/// Magic bytes at the start of every replay file.
+pub const REPLAY_MAGIC: &[u8; 10] = b"TANKREPLAY";
+/// Version of the replay file format.
+pub const REPLAY_VERSION: u8 = 4;
+
+/// Start of a replay file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    /// Number of simulation ticks per second.
+    pub tick_rate: f64,
+    /// Seed of the game's random number generator.
+    pub seed: u64,
+    /// Default tank limits of the recorded game.
+    pub parameters: Parameters,
+}
+
+impl From<&Header> for Vec<u8> {
+    fn from(value: &Header) -> Self {
+        let mut buf = Vec::with_capacity(10 + 1 + 8 + 8 + 45);
+        buf.extend_from_slice(REPLAY_MAGIC);
+        buf.push(REPLAY_VERSION);
+        buf.extend_from_slice(&value.tick_rate.to_be_bytes());
+        buf.extend_from_slice(&value.seed.to_be_bytes());
+        buf.extend_from_slice(&Vec::from(&value.parameters));
+        buf
+    }
+}
+
+impl Header {
+    fn read(buf: &mut &[u8]) -> io::Result<Self> {
+        if read_array(buf)? != *REPLAY_MAGIC {
+            return Err(io::Error::new(ErrorKind::InvalidData, "not a replay file"));
+        }
+        let [version] = read_array(buf)?;
+        if version != REPLAY_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown replay version {version}"),
+            ));
+        }
+        Ok(Header {
+            tick_rate: f64::from_be_bytes(read_array(buf)?),
+            seed: u64::from_be_bytes(read_array(buf)?),
+            parameters: Parameters::from(&read_array(buf)?),
+        })
+    }
+}
+
+/// Statistics of a connected player at the end of a tick.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayerFrame {
+    pub id: u32,
+    pub team: Option<u8>,
+    pub kills: u32,
+    pub deaths: u32,
+    /// Seconds the player's tank has been alive in total.
+    pub survival_time: f32,
+}
+
+impl From<&Player> for PlayerFrame {
+    fn from(value: &Player) -> Self {
+        Self {
+            id: value.id,
+            team: value.team,
+            kills: value.get_kills(),
+            deaths: value.get_deaths(),
+            survival_time: value.survival_time.as_secs_f32(),
+        }
+    }
+}
+
+/// Everything that happened during a single tick, whether the simulation ran or not.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+    /// Number of the tick, counted from the start of the recording.
+    pub tick: u32,
+    /// Phase of the match during the tick.
+    pub state: MatchState,
+    /// Whether the simulation was paused during the tick.
+    pub paused: bool,
+    pub players: Vec<PlayerFrame>,
+    pub tanks: Vec<server::Tank>,
+    pub bullets: Vec<server::Bullet>,
+    /// Control inputs received during the tick.
+    pub controls: Vec<ControlReceived>,
+    pub events: Vec<GameEvent>,
+}
+
+/// A single entry of a replay file, following the [`Header`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Record {
+    /// A new map was loaded, as sent to the players.
+    Map(server::Map),
+    Frame(Frame),
+}
+
+/// Append the number of elements of a list, the lists in a replay can be longer than in
+/// the network protocol.
+fn push_count(buf: &mut Vec<u8>, count: usize) {
+    let count = u16::try_from(count).expect("replay lists hold at most u16::MAX elements");
+    buf.extend_from_slice(&count.to_be_bytes());
+}
+
+/// Read exactly `N` bytes from the front of `buf`.
+fn read_array<const N: usize>(buf: &mut &[u8]) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    buf.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Read an element count written by [`push_count`].
+fn read_count(buf: &mut &[u8]) -> io::Result<usize> {
+    read_array(buf).map(|bytes| usize::from(u16::from_be_bytes(bytes)))
+}
+
+impl From<&GameEvent> for Vec<u8> {
+    fn from(value: &GameEvent) -> Self {
+        let mut buf = vec![];
+        match value {
+            GameEvent::Joined { player, name } => {
+                buf.push(0);
+                buf.extend_from_slice(&player.to_be_bytes());
+                let name = &name.as_bytes()[..name.len().min(server::MAX_NAME_LENGTH)];
+                buf.push(name.len() as u8);
+                buf.extend_from_slice(name);
+            }
+            GameEvent::Left { player } => {
+                buf.push(1);
+                buf.extend_from_slice(&player.to_be_bytes());
+            }
+            GameEvent::Shot { player } => {
+                buf.push(2);
+                buf.extend_from_slice(&player.to_be_bytes());
+            }
+            GameEvent::Died { player, killer } => {
+                buf.push(3);
+                buf.extend_from_slice(&player.to_be_bytes());
+                match killer {
+                    Some(killer) => {
+                        buf.push(1);
+                        buf.extend_from_slice(&killer.to_be_bytes());
+                    }
+                    None => buf.push(0),
+                }
+            }
+        }
+        buf
+    }
+}
+
+impl GameEvent {
+    fn read(buf: &mut &[u8]) -> io::Result<Self> {
+        let [tag] = read_array(buf)?;
+        let player = u32::from_be_bytes(read_array(buf)?);
+        match tag {
+            0 => {
+                let [length] = read_array(buf)?;
+                let mut name = vec![0; usize::from(length)];
+                buf.read_exact(&mut name)?;
+                Ok(GameEvent::Joined {
+                    player,
+                    name: String::from_utf8_lossy(&name).into_owned(),
+                })
+            }
+            1 => Ok(GameEvent::Left { player }),
+            2 => Ok(GameEvent::Shot { player }),
+            3 => {
+                let killer = match read_array(buf)? {
+                    [0] => None,
+                    _ => Some(u32::from_be_bytes(read_array(buf)?)),
+                };
+                Ok(GameEvent::Died { player, killer })
+            }
+            tag => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown event tag {tag}"),
+            )),
+        }
+    }
+}
+
+impl From<&Record> for Vec<u8> {
+    fn from(value: &Record) -> Self {
+        let mut buf = vec![];
+        match value {
+            Record::Map(map) => {
+                buf.push(0);
+                let map = Vec::from(map);
+                push_count(&mut buf, map.len());
+                buf.extend(map);
+            }
+            Record::Frame(frame) => {
+                buf.push(1);
+                buf.extend_from_slice(&frame.tick.to_be_bytes());
+                buf.push(match frame.state {
+                    MatchState::Warmup => 0,
+                    MatchState::Countdown => 1,
+                    MatchState::Playing => 2,
+                    MatchState::RoundOver => 3,
+                });
+                buf.push(u8::from(frame.paused));
+                push_count(&mut buf, frame.players.len());
+                for player in &frame.players {
+                    buf.extend_from_slice(&player.id.to_be_bytes());
+                    buf.push(player.team.unwrap_or(u8::MAX));
+                    buf.extend_from_slice(&player.kills.to_be_bytes());
+                    buf.extend_from_slice(&player.deaths.to_be_bytes());
+                    buf.extend_from_slice(&player.survival_time.to_be_bytes());
+                }
+                push_count(&mut buf, frame.tanks.len());
+                frame
+                    .tanks
+                    .iter()
+                    .for_each(|tank| buf.extend(Vec::from(tank)));
+                push_count(&mut buf, frame.bullets.len());
+                frame
+                    .bullets
+                    .iter()
+                    .for_each(|bullet| buf.extend(Vec::from(bullet)));
+                push_count(&mut buf, frame.controls.len());
+                for control in &frame.controls {
+                    buf.extend_from_slice(&control.player.to_be_bytes());
+                    buf.extend_from_slice(&control.tracks_acceleration_target[0].to_be_bytes());
+                    buf.extend_from_slice(&control.tracks_acceleration_target[1].to_be_bytes());
+                    buf.extend_from_slice(&control.turret_acceleration_target.to_be_bytes());
+                    buf.push(u8::from(control.shoot));
+                }
+                push_count(&mut buf, frame.events.len());
+                frame
+                    .events
+                    .iter()
+                    .for_each(|event| buf.extend(Vec::from(event)));
+            }
+        }
+        buf
+    }
+}
+
+impl Record {
+    fn read(buf: &mut &[u8]) -> io::Result<Self> {
+        match read_array(buf)? {
+            [0] => {
+                let length = read_count(buf)?;
+                let (map, rest) = buf
+                    .split_at_checked(length)
+                    .ok_or(io::Error::from(ErrorKind::UnexpectedEof))?;
+                *buf = rest;
+                server::Map::try_from(map)
+                    .map(Record::Map)
+                    .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+            }
+            [1] => {
+                let tick = u32::from_be_bytes(read_array(buf)?);
+                let state = match read_array(buf)? {
+                    [0] => MatchState::Warmup,
+                    [1] => MatchState::Countdown,
+                    [2] => MatchState::Playing,
+                    [3] => MatchState::RoundOver,
+                    [state] => {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("unknown match state {state}"),
+                        ));
+                    }
+                };
+                let paused = read_array(buf)? != [0];
+                let players = (0..read_count(buf)?)
+                    .map(|_| {
+                        Ok(PlayerFrame {
+                            id: u32::from_be_bytes(read_array(buf)?),
+                            team: Some(read_array::<1>(buf)?[0]).filter(|&team| team != u8::MAX),
+                            kills: u32::from_be_bytes(read_array(buf)?),
+                            deaths: u32::from_be_bytes(read_array(buf)?),
+                            survival_time: f32::from_be_bytes(read_array(buf)?),
+                        })
+                    })
+                    .collect::<io::Result<_>>()?;
+                let tanks = (0..read_count(buf)?)
+                    .map(|_| Ok(server::Tank::from(&read_array(buf)?)))
+                    .collect::<io::Result<_>>()?;
+                let bullets = (0..read_count(buf)?)
+                    .map(|_| Ok(server::Bullet::from(&read_array(buf)?)))
+                    .collect::<io::Result<_>>()?;
+                let controls = (0..read_count(buf)?)
+                    .map(|_| {
+                        Ok(ControlReceived {
+                            player: u32::from_be_bytes(read_array(buf)?),
+                            tracks_acceleration_target: [
+                                f32::from_be_bytes(read_array(buf)?),
+                                f32::from_be_bytes(read_array(buf)?),
+                            ],
+                            turret_acceleration_target: f32::from_be_bytes(read_array(buf)?),
+                            shoot: read_array(buf)? != [0],
+                        })
+                    })
+                    .collect::<io::Result<_>>()?;
+                let events = (0..read_count(buf)?)
+                    .map(|_| GameEvent::read(buf))
+                    .collect::<io::Result<_>>()?;
+                Ok(Record::Frame(Frame {
+                    tick,
+                    state,
+                    paused,
+                    players,
+                    tanks,
+                    bullets,
+                    controls,
+                    events,
+                }))
+            }
+            [tag] => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown record tag {tag}"),
+            )),
+        }
+    }
+}
+
+/// A replay file loaded into memory.
+pub struct Replay {
+    pub header: Header,
+    /// The maps of the match, along with the index of the first frame they are shown in.
+    pub maps: Vec<(usize, server::Map)>,
+    pub frames: Vec<Frame>,
+    /// Names of all players that joined during the match.
+    pub names: HashMap<u32, String>,
+}
+
+impl Replay {
+    /// Load the replay file at `path`.
+    /// A replay cut short, e.g. because the server was killed, is loaded up to its last
+    /// complete record.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = read(path)?;
+        let mut buf = &file[..];
+        let mut replay = Replay {
+            header: Header::read(&mut buf)?,
+            maps: vec![],
+            frames: vec![],
+            names: HashMap::new(),
+        };
+        while !buf.is_empty() {
+            match Record::read(&mut buf) {
+                Ok(Record::Map(map)) => replay.maps.push((replay.frames.len(), map)),
+                Ok(Record::Frame(frame)) => {
+                    for event in &frame.events {
+                        if let GameEvent::Joined { player, name } = event {
+                            replay.names.insert(*player, name.clone());
+                        }
+                    }
+                    replay.frames.push(frame);
+                }
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                    log::warn!(
+                        "The replay {} ends with an incomplete record.",
+                        path.display()
+                    );
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(replay)
+    }
+}
+
+/// Writes every tick to a replay file, including those where the simulation does not run.
+/// Only present if recording is switched on.
+#[derive(Resource)]
+pub struct Recorder {
+    writer: BufWriter<File>,
+    tick: u32,
+}
+
+impl Recorder {
+    /// Create the replay file at `path` and write its [`Header`].
+    pub fn create(path: &Path, header: &Header) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&Vec::from(header))?;
+        Ok(Self { writer, tick: 0 })
+    }
+
+    fn write(&mut self, record: &Record) {
+        if let Err(err) = self.writer.write_all(&Vec::from(record)) {
+            log::error_once!("Writing the replay failed with {err}");
+        }
+    }
+}
+
+/// Append the state of the world at the end of the tick to the replay.
+#[allow(clippy::too_many_arguments)]
+pub fn record_tick(
+    mut recorder: ResMut<Recorder>,
+    state: Res<State<MatchState>>,
+    paused: Res<Paused>,
+    mut events: EventReader<GameEvent>,
+    mut controls: EventReader<ControlReceived>,
+    players: Query<&Player>,
+    joined: Query<&Player, Added<Player>>,
+    tanks: Query<(&Tank, &Transform, &Children, &ChildOf)>,
+    turrets: Query<&Transform, With<Turret>>,
+    bullets: Query<(&Bullet, &Transform)>,
+    maps: Query<(&entities::Map, &Transform, &Children), Added<entities::Map>>,
+    walls: Query<(&Wall, &Transform), Without<entities::Map>>,
+    spawns: Query<(&Spawn, &Transform)>,
+) {
+    for map in &maps {
+        if let server::ServerMessages::MapChange { map, .. } = map_change(map, &walls, &spawns) {
+            recorder.write(&Record::Map(map));
+        }
+    }
+
+    let (tanks, bullets) = world_state(&players, &tanks, &turrets, &bullets);
+    let frame = Frame {
+        tick: recorder.tick,
+        state: *state.get(),
+        paused: paused.0,
+        players: players.iter().map(PlayerFrame::from).collect(),
+        tanks,
+        bullets,
+        controls: controls.read().copied().collect(),
+        events: joined
+            .iter()
+            .map(|player| GameEvent::Joined {
+                player: player.id,
+                name: player.name.clone(),
+            })
+            .chain(events.read().cloned())
+            .collect(),
+    };
+    recorder.write(&Record::Frame(frame));
+    recorder.tick += 1;
+}
+
+/// Make sure everything recorded ends up in the file before the server exits.
+pub fn finish_recording(mut recorder: ResMut<Recorder>) {
+    if let Err(err) = recorder.writer.flush() {
+        log::error!("Writing the replay failed with {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records() {
+        let map = Record::Map(server::Map {
+            name: "Layout-00".to_owned(),
+            bounds: server::Bounds {
+                min: [-10.0, -10.0],
+                max: [10.0, 10.0],
+            },
+            walls: vec![server::Wall {
+                origin: [-10.0, 0.0],
+                direction_length: [20.0, 0.0],
+                normal: [0.0, 1.0],
+            }],
+            spawns: vec![[0.0, 5.0]],
+        });
+        let frame = Record::Frame(Frame {
+            tick: 42,
+            state: MatchState::RoundOver,
+            paused: true,
+            players: vec![PlayerFrame {
+                id: 1,
+                team: Some(0),
+                kills: 2,
+                deaths: 3,
+                survival_time: 4.5,
+            }],
+            tanks: vec![server::Tank {
+                id: 1,
+                team: Some(0),
+                position: [1.0, 2.0],
+                tank_direction: [0.0, 1.0],
+                turret_direction: [1.0, 0.0],
+                health: 100.0,
+            }],
+            bullets: vec![server::Bullet {
+                position: [3.0, 4.0],
+                direction: [5.0, 6.0],
+            }],
+            controls: vec![ControlReceived {
+                player: 1,
+                tracks_acceleration_target: [7.0, 8.0],
+                turret_acceleration_target: 9.0,
+                shoot: true,
+            }],
+            events: vec![
+                GameEvent::Joined {
+                    player: 1,
+                    name: "Player 1".to_owned(),
+                },
+                GameEvent::Shot { player: 1 },
+                GameEvent::Died {
+                    player: 1,
+                    killer: Some(1),
+                },
+                GameEvent::Died {
+                    player: 1,
+                    killer: None,
+                },
+                GameEvent::Left { player: 1 },
+            ],
+        });
+        for record in [map, frame] {
+            let bytes = Vec::from(&record);
+            let mut buf = &bytes[..];
+            assert_eq!(Record::read(&mut buf).unwrap(), record);
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = Vec::from(&Record::Frame(Frame {
+            events: vec![GameEvent::Shot { player: 1 }],
+            ..Default::default()
+        }));
+        for len in 0..bytes.len() {
+            let mut buf = &bytes[..len];
+            assert_eq!(
+                Record::read(&mut buf).unwrap_err().kind(),
+                ErrorKind::UnexpectedEof
+            );
+        }
+    }
+}
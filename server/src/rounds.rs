@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use bevy::{
+    app::AppExit,
+    asset::AssetServer,
+    ecs::{
+        entity::Entity,
+        event::EventWriter,
+        hierarchy::Children,
+        query::With,
+        resource::Resource,
+        system::{Commands, Query, Res, ResMut},
+    },
+    log,
+    state::state::{NextState, State, States},
+    time::Time,
+    transform::components::Transform,
+};
+use rand::{Rng, distr::Distribution, distr::weighted::WeightedIndex, seq::SliceRandom};
+use serde::Deserialize;
+
+use crate::{
+    config::Config,
+    entities::{self, Bullet, GameRng, Player, Spawn},
+    map::Maps,
+    scoring::Departed,
+    systems::spawn_points,
+};
+
+/// Order in which the maps are played.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum Rotation {
+    /// The maps in the order they are listed in the configuration.
+    Sequential,
+    /// A uniformly random map every round.
+    #[default]
+    Random,
+    /// A random map every round, with one weight per map in the order they are listed.
+    /// Maps without a weight get a weight of `1`.
+    Weighted(Vec<f32>),
+}
+
+/// Timing of the match.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Rounds {
+    /// Time before the first round, the players can already drive around but nothing counts.
+    pub warmup: Duration,
+    /// Time before each round in which the tanks are frozen.
+    pub countdown: Duration,
+    /// Length of a round.
+    pub length: Duration,
+    /// Time between the end of a round and the next map.
+    pub round_over: Duration,
+    /// Number of rounds after which the server shuts down, `0` to play forever.
+    pub count: u32,
+    pub rotation: Rotation,
+}
+
+impl Default for Rounds {
+    fn default() -> Self {
+        Self {
+            warmup: Duration::new(10, 0),
+            countdown: Duration::new(3, 0),
+            length: Duration::new(180, 0),
+            round_over: Duration::new(5, 0),
+            count: 0,
+            rotation: Rotation::default(),
+        }
+    }
+}
+
+/// Phases of the match, the simulation only runs during [`MatchState::Warmup`] and
+/// [`MatchState::Playing`].
+#[derive(States, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MatchState {
+    #[default]
+    Warmup,
+    Countdown,
+    Playing,
+    RoundOver,
+}
+
+/// Time left in the current [`MatchState`], and the number of rounds played so far.
+#[derive(Resource, Default)]
+pub struct RoundClock {
+    pub remaining: Duration,
+    pub played: u32,
+}
+
+impl Maps {
+    /// Pick the map of the next round.
+    pub fn next(&self, rotation: &Rotation, rng: &mut impl Rng) -> usize {
+        match rotation {
+            Rotation::Sequential => self
+                .current
+                .map_or(0, |current| (current + 1) % self.loaded.len()),
+            Rotation::Random => rng.random_range(0..self.loaded.len()),
+            Rotation::Weighted(weights) => {
+                let weights =
+                    (0..self.loaded.len()).map(|i| weights.get(i).copied().unwrap_or(1.0));
+                match WeightedIndex::new(weights) {
+                    Ok(distribution) => distribution.sample(rng),
+                    Err(err) => {
+                        log::warn!("Unusable map weights ({err}), picking a random map.");
+                        rng.random_range(0..self.loaded.len())
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Move the match to its next phase once the current one has run out.
+pub fn advance_round(
+    time: Res<Time>,
+    config: Res<Config>,
+    state: Res<State<MatchState>>,
+    mut next_state: ResMut<NextState<MatchState>>,
+    mut clock: ResMut<RoundClock>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // a transition is still waiting to be applied
+    if matches!(*next_state, NextState::Pending(_)) {
+        return;
+    }
+    clock.remaining = clock.remaining.saturating_sub(time.delta());
+    if !clock.remaining.is_zero() {
+        return;
+    }
+
+    let rounds = &config.rounds;
+    let round = clock.played + 1;
+    let (next, remaining) = match state.get() {
+        MatchState::Warmup => (MatchState::Countdown, rounds.countdown),
+        MatchState::Countdown => (MatchState::Playing, rounds.length),
+        MatchState::Playing => {
+            clock.played += 1;
+            (MatchState::RoundOver, rounds.round_over)
+        }
+        MatchState::RoundOver if rounds.count != 0 && clock.played >= rounds.count => {
+            log::info!("All {} rounds were played, shutting down.", rounds.count);
+            exit.write(AppExit::Success);
+            return;
+        }
+        MatchState::RoundOver => (MatchState::Countdown, rounds.countdown),
+    };
+    log::info!("Round {round}: {next:?}");
+    next_state.set(next);
+    clock.remaining = remaining;
+}
+
+/// Statistics gathered during the warm-up do not count.
+pub fn reset_stats(mut players: Query<&mut Player>, mut departed: ResMut<Departed>) {
+    players
+        .iter_mut()
+        .for_each(|mut player| player.reset_stats());
+    departed.0.clear();
+}
+
+/// Replace the current [`entities::Map`] tree by the next map of the rotation.
+pub fn load_map(
+    mut commands: Commands,
+    asset_server: Option<Res<AssetServer>>,
+    config: Res<Config>,
+    mut maps: ResMut<Maps>,
+    mut rng: ResMut<GameRng>,
+    shown: Query<Entity, With<entities::Map>>,
+) {
+    shown
+        .iter()
+        .for_each(|entity| commands.entity(entity).despawn());
+    let index = match &config.map_generator {
+        // a fresh map replaces the one of the last round
+        Some(generator) => {
+            maps.loaded = vec![generator.generate(rng.0.random())];
+            0
+        }
+        None => maps.next(&config.rounds.rotation, &mut rng.0),
+    };
+    maps.loaded[index].spawn(commands, asset_server);
+    maps.current = Some(index);
+}
+
+/// Clear all bullets and give every player a fresh tank, each on a different spawn point
+/// as far as the map has enough of them.
+pub fn respawn_all(
+    mut commands: Commands,
+    asset_server: Option<Res<AssetServer>>,
+    mut players: Query<(&mut Player, Entity)>,
+    bullets: Query<Entity, With<Bullet>>,
+    spawns: Query<(&Spawn, &Transform)>,
+    mut rng: ResMut<GameRng>,
+) {
+    bullets
+        .iter()
+        .for_each(|entity| commands.entity(entity).despawn());
+
+    let mut taken = vec![];
+    for (mut player, entity) in &mut players {
+        let mut points = spawn_points(&spawns, player.team);
+        points.shuffle(&mut rng.0);
+        let position = points
+            .iter()
+            .find(|point| !taken.contains(*point))
+            .or(points.first())
+            .copied()
+            .unwrap_or_default();
+        taken.push(position);
+
+        player.reset_input();
+        player.respawn_timer = None;
+        player.shoot_timer = None;
+        commands.entity(entity).despawn_related::<Children>();
+        player.spawn_tank(entity, position, &mut commands, &asset_server);
+    }
+}
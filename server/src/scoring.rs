@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use bevy::{
+    ecs::{
+        resource::Resource,
+        system::{Query, Res},
+    },
+    time::Time,
+};
+use serde::Deserialize;
+
+use crate::entities::Player;
+
+/// Formula used to rank the players on the leaderboard.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scoring {
+    /// One point per kill, minus one per death (self-kills included).
+    #[default]
+    KillsMinusDeaths,
+    /// One point per kill, deaths are not penalised.
+    Kills,
+    /// One point per second spent alive.
+    SurvivalTime,
+}
+
+impl Scoring {
+    pub fn score(&self, stats: &Stats) -> i64 {
+        match self {
+            Scoring::KillsMinusDeaths => i64::from(stats.kills) - i64::from(stats.deaths),
+            Scoring::Kills => i64::from(stats.kills),
+            Scoring::SurvivalTime => stats.survival_time.as_secs() as i64,
+        }
+    }
+}
+
+/// Snapshot of a player's statistics, as shown on the leaderboard.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub name: String,
+    pub team: Option<u8>,
+    pub kills: u32,
+    pub deaths: u32,
+    pub survival_time: Duration,
+    /// Whether the player is still connected.
+    pub connected: bool,
+}
+
+impl From<&Player> for Stats {
+    fn from(player: &Player) -> Self {
+        Self {
+            name: player.name.clone(),
+            team: player.team,
+            kills: player.get_kills(),
+            deaths: player.get_deaths(),
+            survival_time: player.survival_time,
+            connected: true,
+        }
+    }
+}
+
+/// Statistics of the players that have disconnected, so they stay on the leaderboard.
+#[derive(Resource, Default)]
+pub struct Departed(pub Vec<Stats>);
+
+/// Count the time each player's tank has been alive.
+pub fn track_survival(time: Res<Time>, mut players: Query<&mut Player>) {
+    for mut player in &mut players {
+        if player.respawn_timer.is_none() {
+            player.survival_time += time.delta();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scores() {
+        let stats = Stats {
+            kills: 3,
+            deaths: 5,
+            survival_time: Duration::from_millis(42_900),
+            ..Default::default()
+        };
+        assert_eq!(Scoring::KillsMinusDeaths.score(&stats), -2);
+        assert_eq!(Scoring::Kills.score(&stats), 3);
+        assert_eq!(Scoring::SurvivalTime.score(&stats), 42);
+    }
+}
@@ -3,109 +3,231 @@ use bevy::{
     core_pipeline::core_2d::Camera2d,
     ecs::{
         entity::Entity,
+        event::EventWriter,
         hierarchy::{ChildOf, Children},
-        query::{With, Without},
-        system::{Commands, Query, Res, ResMut},
+        query::{Added, With, Without},
+        schedule::SystemSet,
+        system::{Commands, Local, Query, Res, ResMut},
     },
     log,
-    math::{Quat, Vec2},
-    sprite::Sprite,
+    math::{Dir2, Quat, Vec2, Vec3},
     time::Time,
     transform::components::Transform,
 };
 use messages::{
     client::ClientMessages,
-    server::{self, ServerMessages},
+    server::{
+        self, DisconnectReason, MAX_BULLETS, MAX_SPAWNS, MAX_TANKS, MAX_WALLS, ServerMessages,
+    },
 };
-use std::{collections::HashSet, f32::consts::PI, net::IpAddr, str::FromStr, time::Duration};
+use rand::Rng;
+use serde::Deserialize;
+use std::{collections::HashSet, f32::consts::PI, time::Duration};
 
 use crate::{
+    broadphase::Broadphase,
+    collision::{circle_wall_contact, trace_bullet},
     config::Config,
-    entities::{Bullet, Player, Socket, Spawn, Tank, Turret, Wall},
-    map::Maps,
-    util::{forget_z, forget_z_arr, with_z},
+    entities::{self, Bullet, GameRng, Player, Socket, Spawn, Tank, Turret, Wall},
+    events::{ControlReceived, GameEvent},
+    scoring::{Departed, Stats},
+    teams::choose_team,
+    util::{forget_z, forget_z_arr, load_sprite, with_z},
 };
 
+/// The systems advancing the game simulation.
+/// They only run during the warm-up and while a round is played,
+/// in windowed mode they can also be paused with the space bar.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Simulation;
+
+/// The spawn points of the current map usable by a player of `team`:
+/// the ones reserved for the team and the unreserved ones.
+/// If there are none, every spawn point is usable.
+pub fn spawn_points(spawns: &Query<(&Spawn, &Transform)>, team: Option<u8>) -> Vec<Vec3> {
+    let points: Vec<_> = spawns
+        .iter()
+        .filter(|(spawn, _)| spawn.0.is_none() || spawn.0 == team)
+        .map(|(_, transform)| transform.translation)
+        .collect();
+    if points.is_empty() {
+        spawns
+            .iter()
+            .map(|(_, transform)| transform.translation)
+            .collect()
+    } else {
+        points
+    }
+}
+
+/// Pick a random spawn point of the current map for a player of `team`.
+fn random_spawn(rng: &mut GameRng, spawns: &Query<(&Spawn, &Transform)>, team: Option<u8>) -> Vec3 {
+    let points = spawn_points(spawns, team);
+    if points.is_empty() {
+        return Vec3::default();
+    }
+    points[rng.0.random_range(0..points.len())]
+}
+
 pub fn setup_camera(mut commands: Commands) {
     commands.spawn(Camera2d);
 }
 
-pub fn load_map(commands: Commands, asset_server: Res<AssetServer>, mut maps: ResMut<Maps>) {
-    let index = rand::random_range(0..maps.loaded.len());
-    maps.loaded[index].spawn(commands, asset_server);
-    maps.current = Some(index);
+/// Collect the walls and spawns of a spawned [`entities::Map`] into a
+/// [`ServerMessages::MapChange`].
+pub fn map_change(
+    map: (&entities::Map, &Transform, &Children),
+    walls: &Query<(&Wall, &Transform), Without<entities::Map>>,
+    spawns: &Query<(&Spawn, &Transform)>,
+) -> ServerMessages {
+    let (map, map_transform, children) = map;
+    let mut walls: Vec<_> = walls
+        .iter_many(children)
+        .map(|(wall, transform)| wall.to_message(transform, map_transform))
+        .collect();
+    if walls.len() > MAX_WALLS {
+        log::warn!(
+            "The map \"{}\" has {} walls, only the first {MAX_WALLS} are sent.",
+            map.name,
+            walls.len()
+        );
+        walls.truncate(MAX_WALLS);
+    }
+    let mut spawns: Vec<_> = spawns
+        .iter_many(children)
+        .map(|(_, transform)| forget_z_arr(map_transform.transform_point(transform.translation)))
+        .collect();
+    spawns.truncate(MAX_SPAWNS);
+
+    let points = walls
+        .iter()
+        .flat_map(|wall| {
+            [
+                Vec2::from(wall.origin),
+                Vec2::from(wall.origin) + Vec2::from(wall.direction_length),
+            ]
+        })
+        .chain(spawns.iter().map(|&spawn| Vec2::from(spawn)));
+    let (min, max) = points.fold(
+        (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+        |(min, max), point| (min.min(point), max.max(point)),
+    );
+    let bounds = if min.cmple(max).all() {
+        server::Bounds {
+            min: min.to_array(),
+            max: max.to_array(),
+        }
+    } else {
+        server::Bounds::default()
+    };
+
+    ServerMessages::MapChange {
+        secret: 0,
+        map: server::Map {
+            name: map.name.clone(),
+            bounds,
+            walls,
+            spawns,
+        },
+    }
+}
+
+/// Send a newly spawned [`entities::Map`] to every player.
+pub fn broadcast_map(
+    socket: Res<Socket>,
+    players: Query<&Player>,
+    maps: Query<(&entities::Map, &Transform, &Children), Added<entities::Map>>,
+    walls: Query<(&Wall, &Transform), Without<entities::Map>>,
+    spawns: Query<(&Spawn, &Transform)>,
+) {
+    for map in &maps {
+        let mut msg = map_change(map, &walls, &spawns);
+        for player in &players {
+            if let Some(address) = player.address {
+                msg.change_secret(player.secret);
+                socket.send(&msg, address);
+            }
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn listen_socket(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
+    asset_server: Option<Res<AssetServer>>,
     config: Res<Config>,
     socket: Res<Socket>,
-    spawns: Query<&Transform, With<Spawn>>,
+    spawns: Query<(&Spawn, &Transform)>,
     mut players: Query<(&mut Player, Entity)>,
+    maps: Query<(&entities::Map, &Transform, &Children)>,
+    walls: Query<(&Wall, &Transform), Without<entities::Map>>,
+    mut rng: ResMut<GameRng>,
+    mut received: EventWriter<ControlReceived>,
+    mut next_id: Local<u32>,
 ) {
-    let mut buf = [0; 32];
-    while let Ok((_, addr)) = socket.0.recv_from(&mut buf) {
-        if addr.ip() != IpAddr::from_str("127.0.0.1").unwrap() {
-            log::warn!("Got a message from outside IP: {addr}");
-            continue;
+    // players spawned during this call only show up in `players` after the commands are applied
+    let mut player_count = players.iter().count();
+    let mut team_sizes = vec![0; config.teams.len()];
+    for team in players.iter().filter_map(|(player, _)| player.team) {
+        if let Some(size) = team_sizes.get_mut(usize::from(team)) {
+            *size += 1;
         }
-
-        match ClientMessages::try_from(&buf[..]) {
-            Ok(ClientMessages::Connect { self_port }) => {
+    }
+    let mut buf = [0; 32];
+    while let Ok((len, addr)) = socket.0.recv_from(&mut buf) {
+        match ClientMessages::try_from(&buf[..len]) {
+            Ok(ClientMessages::Connect { self_port, team }) => {
                 if addr.port() == self_port {
-                    match players.iter_mut().find(|(p, _)| p.port == self_port) {
+                    let welcome = match players.iter_mut().find(|(p, _)| p.address == Some(addr)) {
                         Some((mut player, entity)) => {
-                            let position = spawns
-                                .iter()
-                                .nth(rand::random_range(0..spawns.iter().count().max(1)))
-                                .map(|t| t.translation)
-                                .unwrap_or_default();
-                            commands
-                                .entity(entity)
-                                .despawn_related::<Children>()
-                                .with_children(|parent| {
-                                    parent
-                                        .spawn((
-                                            Tank {
-                                                track_max_velocity: player.track_max_velocity,
-                                                radius: player.tank_radius,
-                                                ..Default::default()
-                                            },
-                                            Sprite::from_image(
-                                                asset_server.load(player.tank_sprite_path.clone()),
-                                            ),
-                                            Transform::from_translation(position),
-                                        ))
-                                        .with_child((
-                                            Turret {
-                                                max_velocity: player.turret_max_velocity,
-                                                ..Default::default()
-                                            },
-                                            Sprite::from_image(
-                                                asset_server
-                                                    .load(player.turret_sprite_path.clone()),
-                                            ),
-                                            Transform::from_xyz(0.0, 3.0, 0.0),
-                                        ));
-                                });
-                            player.reset_input()
+                            let position = random_spawn(&mut rng, &spawns, player.team);
+                            commands.entity(entity).despawn_related::<Children>();
+                            player.spawn_tank(entity, position, &mut commands, &asset_server);
+                            player.reset_input();
+                            // the new tank replaces any pending respawn
+                            player.respawn_timer = None;
+                            player.welcome()
+                        }
+                        None if player_count >= config.max_players => {
+                            log::info!("Refused player @ {addr}, the server is full.");
+                            socket.send(
+                                &ServerMessages::Disconnected {
+                                    reason: DisconnectReason::ServerFull,
+                                },
+                                addr,
+                            );
+                            continue;
                         }
-                        None => Player::spawn(
-                            self_port,
-                            rand::random(),
-                            spawns
-                                .iter()
-                                .nth(rand::random_range(0..spawns.iter().count().max(1)))
-                                .map(|t| t.translation)
-                                .unwrap_or_default(),
-                            "tank_body.png".to_owned(),
-                            "tank_turret.png".to_owned(),
-                            "bullet.png".to_owned(),
-                            &mut commands,
-                            &config,
-                            &asset_server,
-                        ),
+                        None => {
+                            player_count += 1;
+                            *next_id += 1;
+                            let team = choose_team(team, &team_sizes);
+                            if let Some(team) = team {
+                                team_sizes[usize::from(team)] += 1;
+                            }
+                            Player::spawn(
+                                *next_id,
+                                addr,
+                                // secrets stay unpredictable, even for a known seed
+                                rand::random(),
+                                team,
+                                random_spawn(&mut rng, &spawns, team),
+                                "tank_body.png".to_owned(),
+                                "tank_turret.png".to_owned(),
+                                "bullet.png".to_owned(),
+                                &mut commands,
+                                &config,
+                                &asset_server,
+                            )
+                        }
+                    };
+                    socket.send(&welcome, addr);
+                    if let Ok(map) = maps.single() {
+                        let mut msg = map_change(map, &walls, &spawns);
+                        if let ServerMessages::Welcome { secret, .. } = welcome {
+                            msg.change_secret(secret);
+                        }
+                        socket.send(&msg, addr);
                     }
                 } else {
                     log::warn!(
@@ -126,12 +248,18 @@ pub fn listen_socket(
                 if addr.port() == self_port {
                     if let Some((mut player, _)) = players
                         .iter_mut()
-                        .find(|(p, _)| p.port == self_port && p.secret == secret)
+                        .find(|(p, _)| p.address == Some(addr) && p.secret == secret)
                     {
                         player.tracks_acceleration_target = tracks_acceleration_target.into();
                         player.turret_acceleration_target = turret_acceleration_target;
                         player.shoot = shoot;
                         player.timeout = Duration::from_micros(0);
+                        received.write(ControlReceived {
+                            player: player.id,
+                            tracks_acceleration_target,
+                            turret_acceleration_target,
+                            shoot,
+                        });
                     } else {
                         log::warn!("message witout a player.");
                     }
@@ -144,60 +272,81 @@ pub fn listen_socket(
                 }
             }
 
-            Err(err) => log::error!("Incorrectly formatted message received: {err:?}"),
+            Err(err) => log::error!("Incorrectly formatted message received: {err}"),
         }
     }
 }
 
-pub fn send_state(
-    socket: Res<Socket>,
-    players: Query<&Player>,
-    tanks: Query<(&Transform, &Children), With<Tank>>,
-    turrets: Query<&Transform, With<Turret>>,
-    bullets: Query<(&Bullet, &Transform)>,
-) {
-    let tanks: Vec<_> = tanks
+/// Collect the network representation of all tanks and bullets.
+pub fn world_state(
+    players: &Query<&Player>,
+    tanks: &Query<(&Tank, &Transform, &Children, &ChildOf)>,
+    turrets: &Query<&Transform, With<Turret>>,
+    bullets: &Query<(&Bullet, &Transform)>,
+) -> (Vec<server::Tank>, Vec<server::Bullet>) {
+    let tanks = tanks
         .iter()
-        .map(|(tank, turret)| server::Tank {
-            position: forget_z_arr(tank.translation),
-            tank_direction: forget_z_arr(tank.up().as_vec3()),
-            turret_direction: forget_z_arr(
-                tank.rotation * turrets.get(turret[0]).unwrap().up().as_vec3(),
-            ),
+        .filter_map(|(tank, transform, turret, parent)| {
+            let player = players.get(parent.parent()).ok()?;
+            Some(server::Tank {
+                id: player.id,
+                team: player.team,
+                position: forget_z_arr(transform.translation),
+                tank_direction: forget_z_arr(transform.up().as_vec3()),
+                turret_direction: forget_z_arr(
+                    transform.rotation * turrets.get(turret[0]).unwrap().up().as_vec3(),
+                ),
+                health: tank.health,
+            })
         })
         .collect();
-    let bullets: Vec<_> = bullets
+    let bullets = bullets
         .iter()
         .map(|(bullet, transform)| server::Bullet {
             position: forget_z_arr(transform.translation),
             direction: bullet.velocity.to_array(),
         })
         .collect();
+    (tanks, bullets)
+}
+
+pub fn send_state(
+    socket: Res<Socket>,
+    players: Query<&Player>,
+    tanks: Query<(&Tank, &Transform, &Children, &ChildOf)>,
+    turrets: Query<&Transform, With<Turret>>,
+    bullets: Query<(&Bullet, &Transform)>,
+) {
+    let (mut tanks, mut bullets) = world_state(&players, &tanks, &turrets, &bullets);
+    tanks.truncate(MAX_TANKS);
+    bullets.truncate(MAX_BULLETS);
 
     let mut msg = ServerMessages::State {
         secret: 0,
+        player_id: 0,
+        own_tank: None,
         tanks,
         bullets,
     };
 
     for player in players {
-        msg.change_secret(player.secret);
-        let res = socket.0.send_to(&msg.to_vec(), ("127.0.0.1", player.port));
-        match res {
-            Ok(_) => {}
-            Err(err) => log::warn!("Sending to player @ {} failed with {}", player.port, err),
+        if let Some(address) = player.address {
+            msg.change_secret(player.secret);
+            msg.change_player(player.id);
+            socket.send(&msg, address);
         }
     }
 }
 
 pub fn apply_controls(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut players: Query<(&mut Player, &Children)>,
+    asset_server: Option<Res<AssetServer>>,
+    mut players: Query<(&mut Player, &Children, Entity)>,
     mut tanks: Query<(&mut Tank, &Transform, &Children), Without<Turret>>,
     mut turrets: Query<(&mut Turret, &Transform), Without<Tank>>,
+    mut events: EventWriter<GameEvent>,
 ) {
-    for (mut player, children) in &mut players {
+    for (mut player, children, player_entity) in &mut players {
         if let Ok((mut tank, tank_transform, children)) = tanks.get_mut(children[0]) {
             tank.track_accelerations = player.tracks_acceleration_target;
 
@@ -213,12 +362,19 @@ pub fn apply_controls(
                             + (tank.radius + player.bullet_radius) * direction;
                         let velocity = forget_z(direction) * player.bullet_speed;
                         commands.spawn((
-                            Bullet::new(velocity, player.bullet_radius, player.bullet_max_bounces),
-                            Sprite::from_image(asset_server.load(&player.bullet_sprite_path)),
+                            Bullet::new(
+                                player_entity,
+                                velocity,
+                                player.bullet_radius,
+                                player.bullet_max_bounces,
+                                player.bullet_damage,
+                            ),
+                            load_sprite(&asset_server, &player.bullet_sprite_path),
                             Transform::from_translation(translation),
                         ));
                         player.shoot = false;
                         player.shoot_timer = Some(Duration::default());
+                        events.write(GameEvent::Shot { player: player.id });
                     }
                 }
                 Err(_) => log::error_once!("Tank without a turret."),
@@ -230,6 +386,7 @@ pub fn apply_controls(
 pub fn move_tanks(
     config: Res<Config>,
     time: Res<Time>,
+    broadphase: Res<Broadphase>,
     tanks: Query<(&mut Tank, &mut Transform)>,
     walls: Query<(&Wall, &Transform), Without<Tank>>,
 ) {
@@ -269,7 +426,7 @@ pub fn move_tanks(
                 }
                 transform.rotate_around(axis, Quat::from_rotation_z(-angle));
             }
-            tank_wall_collision(&tank, &mut transform, &walls);
+            tank_wall_collision(&tank, &mut transform, &walls, &broadphase);
         }
     }
 }
@@ -285,36 +442,154 @@ pub fn move_turrets(time: Res<Time>, turrets: Query<(&mut Turret, &mut Transform
     }
 }
 
-pub fn move_bullets(time: Res<Time>, bullets: Query<(&Bullet, &mut Transform)>) {
-    for (bullet, mut transform) in bullets {
-        transform.translation += with_z(bullet.velocity * time.delta_secs(), 0.0);
+/// Move the bullets with swept collision, so that fast bullets can not skip over walls and
+/// tanks.
+pub fn move_bullets(
+    mut commands: Commands,
+    time: Res<Time>,
+    broadphase: Res<Broadphase>,
+    walls: Query<(&Wall, &Transform), Without<Bullet>>,
+    tanks: Query<(&Tank, &Transform), Without<Bullet>>,
+    mut bullets: Query<(&mut Bullet, &mut Transform, Entity), (Without<Wall>, Without<Tank>)>,
+) {
+    for (mut bullet, mut transform, entity) in &mut bullets {
+        let position = forget_z(transform.translation);
+        // however the bullet bounces, it stays within its travel distance
+        let reach = Vec2::splat((bullet.velocity * time.delta_secs()).length() + bullet.radius);
+        let walls = broadphase
+            .walls
+            .query(position - reach, position + reach)
+            .into_iter()
+            .filter_map(|wall| walls.get(wall).ok())
+            .map(|(wall, transform)| (wall, forget_z(transform.translation)))
+            .collect::<Vec<_>>();
+        let tanks = broadphase
+            .tanks
+            .query(position - reach, position + reach)
+            .into_iter()
+            .filter_map(|entity| {
+                let (tank, transform) = tanks.get(entity).ok()?;
+                Some((entity, forget_z(transform.translation), tank.radius))
+            })
+            .collect::<Vec<_>>();
+        match trace_bullet(&mut bullet, position, time.delta_secs(), &walls, &tanks) {
+            Some(position) => transform.translation = with_z(position, transform.translation.z),
+            None => commands.entity(entity).despawn(),
+        }
     }
 }
 
+/// How tanks that run into each other are handled.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum TankCollision {
+    /// Both tanks are destroyed as soon as they touch, whatever their health.
+    #[default]
+    Destroy,
+    /// The tanks drive through each other, the contact only deals the ram damage.
+    /// A `ram_damage` of at least the tank health destroys both tanks.
+    Overlap,
+    /// The tanks are pushed apart and bounce off each other, as well as taking the ram damage.
+    /// The `restitution` is the fraction of the approach speed kept after the bounce,
+    /// from `0.0` (the tanks stop) to `1.0` (perfectly elastic).
+    Physical { restitution: f32 },
+}
+
+/// Distance up to which physically separated tanks still count as touching,
+/// so that pushing against another tank is a single ram.
+const CONTACT_MARGIN: f32 = 1.0;
+
 pub fn tank_tank_collision(
     mut commands: Commands,
-    tanks: Query<(Entity, &Tank, &Transform, &ChildOf)>, // potentially these could influence the tanks speed?
+    config: Res<Config>,
+    broadphase: Res<Broadphase>,
+    mut tanks: Query<(Entity, &mut Tank, &mut Transform, &ChildOf)>,
+    walls: Query<(&Wall, &Transform), Without<Tank>>,
     mut players: Query<&mut Player>,
+    mut events: EventWriter<GameEvent>,
 ) {
-    let mut delete = vec![];
-    for (i, (entity, tank, transform, parent)) in tanks.iter().enumerate() {
-        let mut push_this = false;
-        for (other, other_tank, other_transform, other_parent) in tanks.iter().skip(i + 1) {
-            if (transform.translation - other_transform.translation).length()
-                < tank.radius + other_tank.radius
-            {
-                delete.push((other, other_parent));
-                push_this = true;
-            }
+    let margin = match config.tank_collision {
+        TankCollision::Destroy | TankCollision::Overlap => 0.0,
+        TankCollision::Physical { .. } => CONTACT_MARGIN,
+    };
+    // pairs of touching tanks, with the damage a ram between them deals
+    let mut touching = vec![];
+    for (first, second) in broadphase.tanks.pairs() {
+        let Ok(
+            [
+                (entity, mut tank, mut transform, _),
+                (other, mut other_tank, mut other_transform, _),
+            ],
+        ) = tanks.get_many_mut([first, second])
+        else {
+            continue;
+        };
+        let offset = forget_z(other_transform.translation - transform.translation);
+        let overlap = tank.radius + other_tank.radius - offset.length();
+        if overlap <= -margin {
+            continue;
         }
-        if push_this && !delete.contains(&(entity, parent)) {
-            delete.push((entity, parent));
+
+        let velocity = tank.velocity(&transform);
+        let other_velocity = other_tank.velocity(&other_transform);
+        let damage = match config.tank_collision {
+            TankCollision::Destroy => f32::INFINITY,
+            _ => config
+                .ram_reference_speed
+                .map_or(config.ram_damage, |reference| {
+                    config.ram_damage * (velocity - other_velocity).length() / reference
+                }),
+        };
+        touching.push((entity, other, damage));
+
+        if let TankCollision::Physical { restitution } = config.tank_collision
+            && overlap > 0.0
+        {
+            // both tanks weigh the same, so each one moves half of the way out
+            let normal = Dir2::new(offset).unwrap_or(Dir2::X);
+            transform.translation -= with_z(normal * overlap / 2.0, 0.0);
+            other_transform.translation += with_z(normal * overlap / 2.0, 0.0);
+            tank_wall_collision(&tank, &mut transform, &walls, &broadphase);
+            tank_wall_collision(&other_tank, &mut other_transform, &walls, &broadphase);
+
+            let approach = (velocity - other_velocity).dot(*normal);
+            if approach > 0.0 {
+                let impulse = normal * approach * (1.0 + restitution) / 2.0;
+                tank.push(&transform, -impulse);
+                other_tank.push(&other_transform, impulse);
+            }
         }
     }
 
-    for (entity, parent) in delete {
-        players.get_mut(parent.parent()).unwrap().death();
-        commands.entity(entity).despawn();
+    for (entity, mut tank, _, parent) in &mut tanks {
+        let contacts = touching
+            .iter()
+            .filter_map(|&(first, second, damage)| {
+                if first == entity {
+                    Some((second, damage))
+                } else if second == entity {
+                    Some((first, damage))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        // only the first tick of a contact counts as a ram
+        let damage: f32 = contacts
+            .iter()
+            .filter(|(other, _)| !tank.contacts.contains(other))
+            .map(|(_, damage)| damage)
+            .sum();
+        tank.contacts = contacts.into_iter().map(|(other, _)| other).collect();
+
+        if damage > 0.0 && tank.damage(damage) {
+            let mut player = players.get_mut(parent.parent()).unwrap();
+            player.death();
+            events.write(GameEvent::Died {
+                player: player.id,
+                killer: None,
+            });
+            commands.entity(entity).despawn();
+        }
     }
 }
 
@@ -322,45 +597,85 @@ pub fn tank_wall_collision(
     tank: &Tank,
     transform: &mut Transform,
     walls: &Query<(&Wall, &Transform), Without<Tank>>,
+    broadphase: &Broadphase,
 ) {
-    let mut correction = Vec2::default();
-
-    for (wall, wall_origin) in walls {
-        let wall_origin = forget_z(wall_origin.translation);
-        let tank_origin = forget_z(transform.translation);
-        // from wikipedia
-        let in_wall_dist = (wall_origin - tank_origin).dot(wall.direction.as_vec2());
-        let dist_vec = (wall_origin - tank_origin) - in_wall_dist * wall.direction;
-        let out_wall_dist = -wall.normal.dot(dist_vec);
-
-        if in_wall_dist.abs() <= wall.half_length + tank.radius * 0.9
-            // && -tank.radius < 6.0 * out_wall_dist
-            && 0.0 < out_wall_dist
-            && out_wall_dist < tank.radius
-        {
-            // tank too close, push it
-            correction += wall.normal * (tank.radius - out_wall_dist);
+    let position = forget_z(transform.translation);
+    let extent = Vec2::splat(tank.radius);
+    // pushing the tank out of one wall at a time keeps it from being pushed twice at corners
+    for wall in broadphase.walls.query(position - extent, position + extent) {
+        let Ok((wall, wall_origin)) = walls.get(wall) else {
+            continue;
+        };
+        if let Some((normal, depth)) = circle_wall_contact(
+            forget_z(transform.translation),
+            tank.radius,
+            wall,
+            forget_z(wall_origin.translation),
+        ) {
+            transform.translation += with_z(normal * depth, 0.0);
         }
     }
-
-    transform.translation += with_z(correction, 0.0);
 }
 
 pub fn tank_bullet_collision(
     mut commands: Commands,
-    mut player: Query<&mut Player>,
-    tanks: Query<(&Tank, &ChildOf, &Transform, Entity), Without<Bullet>>,
+    config: Res<Config>,
+    mut players: Query<&mut Player>,
+    mut tanks: Query<(&mut Tank, &ChildOf, &Transform, Entity), Without<Bullet>>,
     bullets: Query<(&Bullet, &Transform, Entity), Without<Tank>>,
+    mut events: EventWriter<GameEvent>,
+    broadphase: Res<Broadphase>,
 ) {
-    for (tank, parent, transform, entity) in &tanks {
-        for (bullet, bullet_pos, bullet_entity) in &bullets {
+    let mut spent = HashSet::new();
+    for (mut tank, parent, transform, entity) in &mut tanks {
+        // a bullet that stopped at the tank in `move_bullets` only touches it
+        let position = forget_z(transform.translation);
+        let extent = Vec2::splat(tank.radius + 1.0);
+        for bullet_entity in broadphase
+            .bullets
+            .query(position - extent, position + extent)
+        {
+            let Ok((bullet, bullet_pos, bullet_entity)) = bullets.get(bullet_entity) else {
+                continue;
+            };
+            if spent.contains(&bullet_entity) {
+                continue;
+            }
             let distance = transform.translation - bullet_pos.translation;
 
-            if distance.length() < tank.radius + bullet.radius {
-                let mut parent = player.get_mut(parent.parent()).unwrap();
-                parent.death();
-                commands.entity(entity).despawn();
+            if bullet.hit == Some(entity) || distance.length() < tank.radius + bullet.radius {
+                spent.insert(bullet_entity);
                 commands.entity(bullet_entity).despawn();
+                let team = players.get(parent.parent()).unwrap().team;
+                let shooter_team = players.get(bullet.owner()).ok().and_then(|p| p.team);
+                if !config.friendly_fire
+                    && bullet.owner() != parent.parent()
+                    && team.is_some()
+                    && team == shooter_team
+                {
+                    // teammates' bullets are stopped without doing harm
+                    continue;
+                }
+                if !tank.damage(bullet.damage) {
+                    continue;
+                }
+
+                let mut victim = players.get_mut(parent.parent()).unwrap();
+                let player = victim.id;
+                let killer = if bullet.owner() == parent.parent() {
+                    victim.self_kill();
+                    Some(player)
+                } else {
+                    victim.death();
+                    // the shooter might have disconnected since firing
+                    players.get_mut(bullet.owner()).ok().map(|mut shooter| {
+                        shooter.kill();
+                        shooter.id
+                    })
+                };
+                events.write(GameEvent::Died { player, killer });
+                commands.entity(entity).despawn();
+                break;
             }
         }
     }
@@ -368,6 +683,7 @@ pub fn tank_bullet_collision(
 
 pub fn bullet_wall_collision(
     mut commands: Commands,
+    broadphase: Res<Broadphase>,
     walls: Query<(&Wall, &Transform), Without<Bullet>>,
     mut bullets: Query<(&mut Bullet, &mut Transform, Entity), Without<Wall>>,
 ) {
@@ -375,24 +691,30 @@ pub fn bullet_wall_collision(
         let mut correction = Vec2::default();
         let mut despawn = false;
 
-        for (wall, wall_origin) in &walls {
-            let wall_origin = forget_z(wall_origin.translation);
-            let bullet_origin = forget_z(transform.translation);
-            // from wikipedia
-            let in_wall_dist = (wall_origin - bullet_origin).dot(wall.direction.as_vec2());
-            let dist_vec = (wall_origin - bullet_origin) - in_wall_dist * wall.direction;
-            let out_wall_dist = dist_vec.length();
-
-            if in_wall_dist.abs() <= wall.half_length + bullet.radius
-                && out_wall_dist < bullet.radius
-            {
+        let position = forget_z(transform.translation);
+        let extent = Vec2::splat(bullet.radius);
+        for wall in broadphase.walls.query(position - extent, position + extent) {
+            let Ok((wall, wall_origin)) = walls.get(wall) else {
+                continue;
+            };
+            let Some((normal, depth)) = circle_wall_contact(
+                forget_z(transform.translation),
+                bullet.radius,
+                wall,
+                forget_z(wall_origin.translation),
+            ) else {
+                continue;
+            };
+
+            // bullets leaving the wall already bounced off it in `move_bullets`
+            if bullet.velocity.dot(*normal) < 0.0 {
                 if !bullet.add_bounce() {
                     despawn = true;
                     break;
                 }
-                bullet.reflect(wall.normal);
-                correction += wall.normal * (bullet.radius - out_wall_dist);
+                bullet.reflect(normal);
             }
+            correction += normal * depth;
         }
 
         if despawn {
@@ -405,17 +727,21 @@ pub fn bullet_wall_collision(
 
 pub fn bullet_bullet_collision(
     mut commands: Commands,
-    bullets: Query<(&Bullet, &Transform, Entity)>,
+    broadphase: Res<Broadphase>,
+    bullets: Query<(&Bullet, &Transform)>,
 ) {
     let mut despawn = HashSet::new();
-    for (i, (bullet, transform, entity)) in bullets.iter().enumerate() {
-        for (other, o_transform, o_entity) in bullets.iter().skip(i + 1) {
-            if (transform.translation - o_transform.translation).length()
-                < bullet.radius + other.radius
-            {
-                despawn.insert(entity);
-                despawn.insert(o_entity);
-            }
+    for (entity, other) in broadphase.bullets.pairs() {
+        let Ok([(bullet, transform), (other_bullet, other_transform)]) =
+            bullets.get_many([entity, other])
+        else {
+            continue;
+        };
+        if (transform.translation - other_transform.translation).length()
+            < bullet.radius + other_bullet.radius
+        {
+            despawn.insert(entity);
+            despawn.insert(other);
         }
     }
     despawn
@@ -423,48 +749,76 @@ pub fn bullet_bullet_collision(
         .for_each(|entity| commands.entity(entity).despawn());
 }
 
+/// Rebuild the wall grid of the [`Broadphase`] when walls were added or removed.
+pub fn update_wall_grid(
+    mut broadphase: ResMut<Broadphase>,
+    walls: Query<(&Wall, &Transform, Entity)>,
+    added: Query<(), Added<Wall>>,
+) {
+    let count = walls.iter().len();
+    if added.is_empty() && count == broadphase.wall_count {
+        return;
+    }
+    broadphase.walls.clear();
+    broadphase.wall_count = count;
+    for (wall, transform, entity) in &walls {
+        let center = forget_z(transform.translation);
+        let end = wall.direction * wall.half_length;
+        broadphase.walls.insert(
+            entity,
+            (center - end).min(center + end),
+            (center - end).max(center + end),
+        );
+    }
+}
+
+/// Refill the tank grid of the [`Broadphase`] with the current positions of the tanks.
+/// The tanks are inserted with the contact margin of [`TankCollision::Physical`].
+pub fn update_tank_grid(
+    mut broadphase: ResMut<Broadphase>,
+    tanks: Query<(&Tank, &Transform, Entity)>,
+) {
+    broadphase.tanks.clear();
+    for (tank, transform, entity) in &tanks {
+        let position = forget_z(transform.translation);
+        let extent = Vec2::splat(tank.radius + CONTACT_MARGIN);
+        broadphase
+            .tanks
+            .insert(entity, position - extent, position + extent);
+    }
+}
+
+/// Refill the bullet grid of the [`Broadphase`] with the current positions of the bullets.
+pub fn update_bullet_grid(
+    mut broadphase: ResMut<Broadphase>,
+    bullets: Query<(&Bullet, &Transform, Entity)>,
+) {
+    broadphase.bullets.clear();
+    for (bullet, transform, entity) in &bullets {
+        let position = forget_z(transform.translation);
+        let extent = Vec2::splat(bullet.radius);
+        broadphase
+            .bullets
+            .insert(entity, position - extent, position + extent);
+    }
+}
+
 pub fn player_respawn(
     mut commands: Commands,
     config: Res<Config>,
     time: Res<Time>,
-    asset_server: Res<AssetServer>,
+    asset_server: Option<Res<AssetServer>>,
     mut players: Query<(&mut Player, Entity)>,
-    spawns: Query<&Transform, With<Spawn>>,
+    spawns: Query<(&Spawn, &Transform)>,
+    mut rng: ResMut<GameRng>,
 ) {
     for (mut player, entity) in &mut players {
         if let Some(timer) = &mut player.respawn_timer {
             *timer += time.delta();
 
             if config.respawn_delay <= *timer {
-                commands.entity(entity).with_children(|parent| {
-                    parent
-                        .spawn((
-                            Tank {
-                                track_velocities: Vec2::default(),
-                                track_accelerations: Vec2::default(),
-                                radius: player.tank_radius,
-                                track_max_velocity: player.track_max_velocity,
-                            },
-                            Transform::from_translation(
-                                spawns
-                                    .iter()
-                                    .nth(rand::random_range(0..spawns.iter().count().max(1)))
-                                    .map(|t| t.translation)
-                                    .unwrap_or_default(),
-                            ),
-                            Sprite::from_image(asset_server.load(player.tank_sprite_path.clone())),
-                        ))
-                        .with_child((
-                            Turret {
-                                max_velocity: player.turret_max_velocity,
-                                ..Default::default()
-                            },
-                            Transform::from_xyz(0.0, 3.0, 0.0),
-                            Sprite::from_image(
-                                asset_server.load(player.turret_sprite_path.clone()),
-                            ),
-                        ));
-                });
+                let position = random_spawn(&mut rng, &spawns, player.team);
+                player.spawn_tank(entity, position, &mut commands, &asset_server);
                 player.respawn_timer = None;
             }
         }
@@ -486,17 +840,86 @@ pub fn shoot_countdown(time: Res<Time>, mut players: Query<&mut Player>) {
 pub fn player_disconnect(
     time: Res<Time>,
     config: Res<Config>,
+    socket: Res<Socket>,
     players: Query<(&mut Player, Entity)>,
+    mut departed: ResMut<Departed>,
+    mut events: EventWriter<GameEvent>,
     mut commands: Commands,
 ) {
     for (mut player, entity) in players {
         player.timeout += time.delta();
 
         if player.timeout > config.inactivity_timeout {
-            // TODO: Save score.
-            // TODO: Send message to player.
+            departed.0.push(Stats {
+                connected: false,
+                ..Stats::from(&*player)
+            });
+            if let Some(address) = player.address {
+                socket.send(
+                    &ServerMessages::Disconnected {
+                        reason: DisconnectReason::Inactivity,
+                    },
+                    address,
+                );
+            }
+            events.write(GameEvent::Left { player: player.id });
             // Despawn player.
             commands.entity(entity).despawn();
         }
     }
 }
+
+/// Let every player know that the server is going away.
+pub fn disconnect_all(socket: Res<Socket>, players: Query<&Player>) {
+    let msg = ServerMessages::Disconnected {
+        reason: DisconnectReason::ServerShutdown,
+    };
+    for address in players.iter().filter_map(|player| player.address) {
+        socket.send(&msg, address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use bevy::ecs::{event::Events, system::RunSystemOnce, world::World};
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_reconnect_cancels_respawn() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_nonblocking(true).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = client.local_addr().unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(Config::default());
+        world.insert_resource(GameRng(SeedableRng::seed_from_u64(0)));
+        world.init_resource::<Events<ControlReceived>>();
+        let mut player = Player::default();
+        player.address = Some(address);
+        player.respawn_timer = Some(Duration::from_secs(1));
+        let player = world.spawn(player).id();
+
+        let mut connect = ClientMessages::connect();
+        connect.set_port(address.port());
+        client
+            .send_to(&Vec::from(&connect), server.local_addr().unwrap())
+            .unwrap();
+        world.insert_resource(Socket(server));
+        // the datagram may need a moment to arrive on the non-blocking socket
+        for _ in 0..100 {
+            world.run_system_once(listen_socket).unwrap();
+            if world.entity(player).contains::<Children>() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(world.entity(player).contains::<Children>());
+        assert_eq!(world.get::<Player>(player).unwrap().respawn_timer, None);
+    }
+}
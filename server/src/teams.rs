@@ -0,0 +1,46 @@
+use bevy::color::Color;
+use serde::Deserialize;
+
+/// A team declared in the configuration.
+/// Teams are identified by their index in the list of teams.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Team {
+    /// Name shown on the leaderboard.
+    pub name: String,
+    /// Colour (sRGB, `0.0` to `1.0`) the tanks of the team are tinted with.
+    pub color: [f32; 3],
+}
+
+impl Team {
+    pub fn color(&self) -> Color {
+        let [red, green, blue] = self.color;
+        Color::srgb(red, green, blue)
+    }
+}
+
+/// Pick the team of a new player.
+/// The `requested` team is honoured if it exists, otherwise the player joins the smallest team.
+/// `sizes` holds the number of players in each team, there are no teams if it is empty.
+pub fn choose_team(requested: Option<u8>, sizes: &[usize]) -> Option<u8> {
+    requested
+        .filter(|&team| usize::from(team) < sizes.len())
+        .or_else(|| {
+            (0..sizes.len())
+                .min_by_key(|&team| sizes[team])
+                .and_then(|team| u8::try_from(team).ok())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_team() {
+        assert_eq!(choose_team(None, &[]), None);
+        assert_eq!(choose_team(Some(1), &[]), None);
+        assert_eq!(choose_team(None, &[2, 1, 1]), Some(1));
+        assert_eq!(choose_team(Some(0), &[2, 1, 1]), Some(0));
+        assert_eq!(choose_team(Some(3), &[2, 1, 1]), Some(1));
+    }
+}
@@ -5,16 +5,22 @@ use bevy::{
         entity::Entity,
         hierarchy::Children,
         query::With,
-        system::{Commands, Query},
+        system::{Commands, Query, Res},
     },
     log,
+    text::TextColor,
     ui::{
         AlignItems, BackgroundColor, BorderRadius, BoxSizing, Display, GridAutoFlow, JustifyItems,
         Node, PositionType, RepeatedGridTrack, UiRect, Val, widget::Text,
     },
 };
 
-use crate::entities::Player;
+use crate::{
+    config::Config,
+    entities::Player,
+    scoring::{Departed, Stats},
+    teams::Team,
+};
 
 #[derive(Component)]
 #[require(Node)]
@@ -40,7 +46,7 @@ pub fn setup_leaderboard(mut commands: Commands) {
             row_gap: Val::Px(2.0),
             column_gap: Val::Px(4.0),
             grid_auto_flow: GridAutoFlow::Row,
-            grid_template_columns: vec![RepeatedGridTrack::auto(2)],
+            grid_template_columns: vec![RepeatedGridTrack::auto(5)],
             ..Default::default()
         },
         BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
@@ -50,26 +56,85 @@ pub fn setup_leaderboard(mut commands: Commands) {
 
 pub fn show_leaderboard(
     mut commands: Commands,
+    config: Res<Config>,
     leaderboard: Query<Entity, With<Leaderboard>>,
     players: Query<&Player>,
+    departed: Res<Departed>,
 ) {
-    if players.is_empty() {
+    if players.is_empty() && departed.0.is_empty() {
         return;
     }
-    let mut players = players
+    let mut rows = players
+        .iter()
+        .map(Stats::from)
+        .chain(departed.0.iter().cloned())
+        .map(|stats| (config.scoring.score(&stats), stats))
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .reverse()
+            .then(b.1.connected.cmp(&a.1.connected))
+            .then(a.1.name.cmp(&b.1.name))
+    });
+    // the teams are ranked by the sum of their members' scores, above the players
+    let mut teams = config
+        .teams
         .iter()
-        .map(|player| (player.port, player.get_deaths()))
+        .enumerate()
+        .map(|(index, team)| {
+            let members = rows
+                .iter()
+                .filter(|(_, stats)| stats.team.is_some_and(|t| usize::from(t) == index));
+            let mut total = (0, 0, 0, 0);
+            for (score, stats) in members {
+                total.0 += stats.kills;
+                total.1 += stats.deaths;
+                total.2 += score;
+                total.3 += usize::from(stats.connected);
+            }
+            (team, total)
+        })
         .collect::<Vec<_>>();
-    players.sort_by(|a, b| a.1.cmp(&b.1).reverse().then(a.0.cmp(&b.0)));
+    teams.sort_by(|a, b| a.1.2.cmp(&b.1.2).reverse());
+    let color = |team: Option<u8>| {
+        TextColor(
+            team.and_then(|team| config.teams.get(usize::from(team)))
+                .map_or(Color::WHITE, Team::color),
+        )
+    };
     match leaderboard.single() {
         Ok(entity) => {
             commands
                 .entity(entity)
                 .despawn_related::<Children>()
                 .with_children(|parent| {
-                    players.iter().for_each(|(port, deaths)| {
-                        parent.spawn(Text::new(format!("Player {}:", port)));
-                        parent.spawn(Text::new(format!("{}", deaths)));
+                    ["Name", "Kills", "Deaths", "Score", "Status"]
+                        .into_iter()
+                        .for_each(|header| {
+                            parent.spawn(Text::new(header));
+                        });
+                    teams
+                        .iter()
+                        .for_each(|(team, (kills, deaths, score, connected))| {
+                            parent.spawn((
+                                Text::new(format!("{}:", team.name)),
+                                TextColor(team.color()),
+                            ));
+                            parent.spawn(Text::new(format!("{kills}")));
+                            parent.spawn(Text::new(format!("{deaths}")));
+                            parent.spawn(Text::new(format!("{score}")));
+                            parent.spawn(Text::new(format!("{connected} connected")));
+                        });
+                    rows.iter().for_each(|(score, stats)| {
+                        parent.spawn((Text::new(format!("{}:", stats.name)), color(stats.team)));
+                        parent.spawn(Text::new(format!("{}", stats.kills)));
+                        parent.spawn(Text::new(format!("{}", stats.deaths)));
+                        parent.spawn(Text::new(format!("{}", score)));
+                        parent.spawn(Text::new(if stats.connected {
+                            "connected"
+                        } else {
+                            "left"
+                        }));
                     });
                 });
         }
@@ -1,4 +1,9 @@
-use bevy::math::{Vec2, Vec3};
+use bevy::{
+    asset::{AssetPath, AssetServer},
+    ecs::system::Res,
+    math::{Vec2, Vec3},
+    sprite::Sprite,
+};
 
 pub fn forget_z(vec: Vec3) -> Vec2 {
     Vec2 { x: vec.x, y: vec.y }
@@ -15,3 +20,15 @@ pub fn with_z(vec: Vec2, z: f32) -> Vec3 {
 pub fn with_z_arr(arr: [f32; 2], z: f32) -> Vec3 {
     Vec3 { x: arr[0], y: arr[1], z }
 }
+
+/// Load the image at `path` as a [`Sprite`].
+/// Without an [`AssetServer`] (when running headless) an empty sprite is returned instead.
+pub fn load_sprite<'a>(
+    asset_server: &Option<Res<AssetServer>>,
+    path: impl Into<AssetPath<'a>>,
+) -> Sprite {
+    match asset_server {
+        Some(asset_server) => Sprite::from_image(asset_server.load(path)),
+        None => Sprite::default(),
+    }
+}
@@ -0,0 +1,500 @@
+use std::{
+    collections::HashMap,
+    f32::consts::TAU,
+    fmt::{self, Display},
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use bevy::{asset::io::file::FileAssetReader, math::Vec2};
+use messages::server::{MAX_SPAWNS, MAX_WALLS};
+
+use crate::{
+    config::Config,
+    map::{Map, Segment, Shape, strip_comments},
+};
+
+/// Number of rays cast from a spawn to check that it is enclosed by walls.
+const RAYS: u16 = 16;
+/// Tolerance for coordinates and directions that should be equal.
+const EPSILON: f32 = 1e-3;
+
+/// Position in a file, both starting at 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    const START: Self = Self { line: 1, column: 1 };
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The map loads, but probably not as intended.
+    Warning,
+    /// The map is not loaded.
+    Error,
+}
+
+/// A mistake found in a map file.
+#[derive(Debug)]
+pub struct Problem {
+    pub location: Location,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {severity}: {}", self.location, self.message)
+    }
+}
+
+/// Where the values of a map file start.
+#[derive(Default)]
+struct Locations {
+    /// The values of the top level object, by key.
+    values: HashMap<String, Location>,
+    /// The elements of the top level arrays, by key.
+    elements: HashMap<String, Vec<Location>>,
+}
+
+impl Locations {
+    /// Scan the JSON, which must not contain comments anymore.
+    fn scan(json: &str) -> Self {
+        let mut locations = Self::default();
+        let (mut depth, mut key) = (0, String::new());
+        let (mut in_string, mut escaped, mut in_key) = (false, false, false);
+        let (mut expect_key, mut expect_value, mut expect_element) = (false, false, false);
+        for (line, text) in json.lines().enumerate() {
+            for (column, c) in text.chars().enumerate() {
+                let location = Location {
+                    line: line + 1,
+                    column: column + 1,
+                };
+                if in_string {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => in_string = false,
+                        _ => {}
+                    }
+                    if in_string && in_key {
+                        key.push(c);
+                    }
+                    in_key &= in_string;
+                    continue;
+                }
+                if c.is_whitespace() {
+                    continue;
+                }
+                if depth == 1 && expect_value {
+                    locations.values.insert(key.clone(), location);
+                    expect_value = false;
+                }
+                if depth == 2 && expect_element {
+                    locations
+                        .elements
+                        .entry(key.clone())
+                        .or_default()
+                        .push(location);
+                    expect_element = false;
+                }
+                match c {
+                    '"' => {
+                        in_string = true;
+                        if depth == 1 && expect_key {
+                            (in_key, expect_key) = (true, false);
+                            key.clear();
+                        }
+                    }
+                    '{' | '[' => {
+                        depth += 1;
+                        expect_key = depth == 1;
+                        expect_element = depth == 2 && c == '[';
+                    }
+                    '}' | ']' => depth -= 1,
+                    ':' if depth == 1 => expect_value = true,
+                    ',' if depth == 1 => expect_key = true,
+                    // the elements of arrays nested in the top level object
+                    ',' if depth == 2 => expect_element = locations.elements.contains_key(&key),
+                    _ => {}
+                }
+            }
+        }
+        locations
+    }
+
+    fn value(&self, key: &str) -> Location {
+        self.values.get(key).copied().unwrap_or(Location::START)
+    }
+
+    fn element(&self, key: &str, index: usize) -> Location {
+        self.elements
+            .get(key)
+            .and_then(|elements| elements.get(index))
+            .copied()
+            .unwrap_or(Location::START)
+    }
+}
+
+/// Distance from `point` to the closest point of `segment`.
+pub(crate) fn distance(segment: &Segment, point: Vec2) -> f32 {
+    let edge = segment.to - segment.from;
+    let along = ((point - segment.from).dot(edge) / edge.length_squared()).clamp(0.0, 1.0);
+    point.distance(segment.from + along * edge)
+}
+
+/// Distance along `direction` at which a ray from `origin` crosses `segment`.
+fn ray_hit(origin: Vec2, direction: Vec2, segment: &Segment) -> Option<f32> {
+    let edge = segment.to - segment.from;
+    let denominator = direction.perp_dot(edge);
+    if denominator.abs() < f32::EPSILON {
+        return None;
+    }
+    let offset = segment.from - origin;
+    let time = offset.perp_dot(edge) / denominator;
+    let along = offset.perp_dot(direction) / denominator;
+    (time > 0.0 && (0.0..=1.0).contains(&along)).then_some(time)
+}
+
+/// Whether every ray cast from `point` first runs into the front of a wall.
+/// Rays escaping the map or running into the back of a wall start outside the playing area.
+fn enclosed(point: Vec2, segments: &[(Segment, Location)]) -> bool {
+    (0..RAYS).all(|i| {
+        let direction = Vec2::from_angle(TAU * (f32::from(i) + 0.5) / f32::from(RAYS));
+        // distance of the closest wall, and whether one of the walls there faces the ray
+        let mut closest: Option<(f32, bool)> = None;
+        for (segment, _) in segments {
+            let Some(time) = ray_hit(point, direction, segment) else {
+                continue;
+            };
+            let facing = segment.normal.dot(direction) < 0.0;
+            closest = match closest {
+                Some((closest, faced)) if (closest - time).abs() < EPSILON => {
+                    Some((closest, faced || facing))
+                }
+                Some((closest, _)) if closest < time => continue,
+                _ => Some((time, facing)),
+            };
+        }
+        closest.is_some_and(|(_, facing)| facing)
+    })
+}
+
+/// Check a map file, reporting every problem found.
+/// The map is returned if it could be parsed, even if there were errors.
+pub fn validate(json: &str, assets: &Path, tank_radius: f32) -> (Option<Map>, Vec<Problem>) {
+    let json = strip_comments(json);
+    let locations = Locations::scan(&json);
+    let map = match Map::parse(&json) {
+        Ok(map) => map,
+        Err(err) => {
+            // errors raised after parsing, like an unsupported version, have no position
+            let location = match err.line() {
+                0 => locations.value("version"),
+                line => Location {
+                    line,
+                    column: err.column(),
+                },
+            };
+            let message = err.to_string();
+            let suffix = format!(" at line {} column {}", err.line(), err.column());
+            let problem = Problem {
+                location,
+                severity: Severity::Error,
+                message: message.strip_suffix(&suffix).unwrap_or(&message).to_owned(),
+            };
+            return (None, vec![problem]);
+        }
+    };
+
+    let mut problems = vec![];
+    let mut report = |location, severity, message| {
+        problems.push(Problem {
+            location,
+            severity,
+            message,
+        })
+    };
+
+    let mut segments = vec![];
+    for (i, wall) in map.walls.iter().enumerate() {
+        let location = locations.element("walls", i);
+        let Some(segment) = wall.segment() else {
+            report(location, Severity::Error, "the wall has no length".into());
+            continue;
+        };
+        if let Some(normal) = wall.normal {
+            let direction = (segment.to - segment.from).normalize();
+            match Vec2::from(normal).try_normalize() {
+                None => report(location, Severity::Error, "the normal is zero".into()),
+                Some(normal) if normal.dot(direction).abs() > EPSILON => report(
+                    location,
+                    Severity::Error,
+                    format!("the normal {normal} is not perpendicular to the wall"),
+                ),
+                Some(_) => {}
+            }
+        }
+        segments.push((segment, location));
+    }
+    for (i, shape) in map.shapes.iter().enumerate() {
+        let location = locations.element("shapes", i);
+        let message = match shape {
+            Shape::Polygon { polygon, .. } if polygon.len() < 3 => {
+                "a polygon needs at least 3 points"
+            }
+            Shape::Polyline { polyline } if polyline.len() < 2 => {
+                "a polyline needs at least 2 points"
+            }
+            Shape::Circle { radius, .. } if *radius <= 0.0 => "the radius has to be positive",
+            Shape::Circle { segments, .. } if *segments < 3 => "a circle needs at least 3 segments",
+            _ => "",
+        };
+        if !message.is_empty() {
+            report(location, Severity::Error, message.into());
+        }
+        segments.extend(
+            shape
+                .segments()
+                .into_iter()
+                .map(|segment| (segment, location)),
+        );
+    }
+
+    // the players would only be sent part of the map
+    if let Some((_, location)) = segments.get(MAX_WALLS) {
+        let message = format!(
+            "the map has {} walls, at most {MAX_WALLS} can be sent to the players",
+            segments.len()
+        );
+        report(*location, Severity::Error, message);
+    }
+    if map.spawns.len() > MAX_SPAWNS {
+        let message = format!(
+            "the map has {} spawns, at most {MAX_SPAWNS} can be sent to the players",
+            map.spawns.len()
+        );
+        report(
+            locations.element("spawns", MAX_SPAWNS),
+            Severity::Error,
+            message,
+        );
+    }
+
+    for (i, (segment, location)) in segments.iter().enumerate() {
+        let duplicate = segments[..i].iter().find(|(other, _)| {
+            let same = other.from.abs_diff_eq(segment.from, EPSILON)
+                && other.to.abs_diff_eq(segment.to, EPSILON);
+            let reversed = other.from.abs_diff_eq(segment.to, EPSILON)
+                && other.to.abs_diff_eq(segment.from, EPSILON);
+            (same || reversed) && other.normal.dot(*segment.normal) > 1.0 - EPSILON
+        });
+        if let Some((_, other)) = duplicate {
+            let message = format!("duplicate of the wall at {other}");
+            report(*location, Severity::Warning, message);
+        }
+    }
+
+    for (i, spawn) in map.spawns.iter().enumerate() {
+        let location = locations.element("spawns", i);
+        let position = spawn.position();
+        let blocking = segments
+            .iter()
+            .find(|(segment, _)| distance(segment, position) < tank_radius);
+        if let Some((_, wall)) = blocking {
+            let message = format!("the spawn {position} is inside the wall at {wall}");
+            report(location, Severity::Error, message);
+        } else if !enclosed(position, &segments) {
+            let message = format!("the spawn {position} is outside the area enclosed by walls");
+            report(location, Severity::Error, message);
+        }
+        let overlapping = map.spawns[..i]
+            .iter()
+            .position(|other| other.position().distance(position) < 2.0 * tank_radius);
+        if let Some(other) = overlapping {
+            let other = locations.element("spawns", other);
+            let message = format!("the spawn overlaps the spawn at {other}");
+            report(location, Severity::Warning, message);
+        }
+    }
+
+    if let Some(path) = &map.background_path
+        && !assets.join(path).is_file()
+    {
+        report(
+            locations.value("background_path"),
+            Severity::Warning,
+            format!("background image {} not found", assets.join(path).display()),
+        );
+    }
+
+    problems.sort_by_key(|problem| problem.location);
+    (Some(map), problems)
+}
+
+/// Read and check the map files, printing their problems.
+/// Returns the maps without errors and the number of errors.
+pub fn load_maps(paths: &[PathBuf], config: &Config) -> (Vec<Map>, usize) {
+    // where bevy's `AssetServer` looks for the background images
+    let assets = FileAssetReader::get_base_path().join("assets");
+    let mut maps = vec![];
+    let mut errors = 0;
+    for path in paths {
+        let json = match read_to_string(path) {
+            Ok(json) => json,
+            Err(err) => {
+                println!("{}: error: {err}", path.display());
+                errors += 1;
+                continue;
+            }
+        };
+        let (map, problems) = validate(&json, &assets, config.tank_radius);
+        for problem in &problems {
+            println!("{}:{problem}", path.display());
+        }
+        let failed = problems
+            .iter()
+            .filter(|problem| problem.severity == Severity::Error)
+            .count();
+        errors += failed;
+        match map {
+            Some(map) if failed == 0 => maps.push(map),
+            _ => println!("Skipping map {}", path.display()),
+        }
+    }
+    (maps, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(json: &str) -> Vec<(usize, usize, Severity)> {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        validate(json, &assets, 12.0)
+            .1
+            .into_iter()
+            .map(|problem| {
+                (
+                    problem.location.line,
+                    problem.location.column,
+                    problem.severity,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_shipped_maps() {
+        assert!(problems(include_str!("../assets/maps/map.jsonc")).is_empty());
+        assert!(problems(include_str!("../assets/maps/layout-00.jsonc")).is_empty());
+    }
+
+    #[test]
+    fn test_locations() {
+        let json = r#"{
+    "name": "a \"b\" [c]", "walls": [ { "from": [0, 0] },
+        { "from": [1, 1] } ], "spawns": [[0, 0], [1, 1]]
+}"#;
+        let locations = Locations::scan(json);
+        assert_eq!(
+            locations.value("name"),
+            Location {
+                line: 2,
+                column: 13
+            }
+        );
+        assert_eq!(
+            locations.element("walls", 0),
+            Location {
+                line: 2,
+                column: 39
+            }
+        );
+        assert_eq!(
+            locations.element("walls", 1),
+            Location { line: 3, column: 9 }
+        );
+        assert_eq!(
+            locations.element("spawns", 1),
+            Location {
+                line: 3,
+                column: 50
+            }
+        );
+    }
+
+    #[test]
+    fn test_problems() {
+        use Severity::{Error, Warning};
+
+        let json = r#"{
+    "version": 2, "name": "Bad", // comment
+    "background_path": "missing.png",
+    "walls": [
+        { "from": [0, 0], "to": [0, 0] },
+        { "from": [-100, 100], "to": [100, 100], "normal": [1, 1] }
+    ],
+    "shapes": [
+        { "polygon": [[-100, -100], [100, -100], [100, 100], [-100, 100]], "inward": true },
+        { "circle": [0, 0], "radius": -1 }
+    ],
+    "spawns": [[50, 50], [55, 50], [-95, 0], [300, 0]]
+}"#;
+        assert_eq!(
+            problems(json),
+            [
+                (3, 24, Warning),
+                (5, 9, Error),
+                (6, 9, Error),
+                (10, 9, Error),
+                (12, 26, Warning),
+                (12, 36, Error),
+                (12, 46, Error),
+            ]
+        );
+        let newer = r#"{
+  "version": 3, "name": "", "background_path": "map_imgs/map.png", "spawns": []
+}"#;
+        assert_eq!(problems(newer), [(2, 14, Error)]);
+        assert_eq!(problems("{\n  \"name\": }"), [(2, 11, Error)]);
+    }
+
+    #[test]
+    fn test_too_large() {
+        let polygon = (0..MAX_WALLS + 1)
+            .map(|i| Vec2::from_angle(i as f32 * TAU / (MAX_WALLS + 1) as f32) * 1000.0)
+            .map(|point| format!("[{}, {}]", point.x, point.y))
+            .collect::<Vec<_>>();
+        let spawns = (0..MAX_SPAWNS + 1)
+            .map(|i| format!("        [{}, {}]", i % 16 * 30, i / 16 * 30))
+            .collect::<Vec<_>>();
+        let json = format!(
+            "{{\n    \"version\": 2, \"name\": \"Large\",\n    \"shapes\": [{{ \"polygon\": [{}], \
+             \"inward\": true }}],\n    \"spawns\": [\n{}\n    ]\n}}",
+            polygon.join(", "),
+            spawns.join(",\n")
+        );
+        assert_eq!(
+            problems(&json),
+            [
+                (3, 16, Severity::Error),
+                (5 + MAX_SPAWNS, 9, Severity::Error)
+            ]
+        );
+    }
+}